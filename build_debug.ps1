@@ -1,3 +1,3 @@
-cargo +nightly build
+cargo +nightly build --features probe
 copy target/wasm32-unknown-unknown/debug/neoweb.wasm pkg/neoweb.wasm
 gzip pkg/neoweb.wasm -1 -f
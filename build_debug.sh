@@ -1,5 +1,5 @@
 #!/bin/bash
 set -e
-cargo +nightly build
+cargo +nightly build --features probe
 cp target/wasm32-unknown-unknown/debug/neoweb.wasm pkg/neoweb.wasm
 gzip pkg/neoweb.wasm -1 -f
@@ -1,35 +1,56 @@
-async function fetchFileBytesCompressed(url) {
+async function fetchFileBytes(url) {
     const response = await fetch(url);
 
     if (!response.ok) {
         throw new Error(`Failed to fetch file: ${response.status}`);
     }
 
-    const decompressedStream = response.body.pipeThrough(new DecompressionStream("gzip"));
-
-    const arrayBuffer = await new Response(decompressedStream).arrayBuffer();
+    const arrayBuffer = await response.arrayBuffer();
     const byteArray = new Uint8Array(arrayBuffer);
 
     return byteArray;
 }
-async function fetchFileBytes(url) {
-    const response = await fetch(url);
 
-    if (!response.ok) {
-        throw new Error(`Failed to fetch file: ${response.status}`);
+let openosImage = null;
+async function getOpenosImage() {
+    if (openosImage == null) {
+        openosImage = new window.nwBaseImage(await fetchFileBytes('openos.ntar.gz'));
     }
-
-    const arrayBuffer = await response.arrayBuffer();
-    const byteArray = new Uint8Array(arrayBuffer);
-
-    return byteArray;
+    return openosImage;
 }
 
 async function addDefaultComputer(parent) {
     const computer = new window.nwComputer();
     const screen = new window.nwScreen(computer, parent, 1, 80, 25);
     computer.add_eeprom(await fetchFileBytes('luaBios.lua'));
-    computer.add_vfs(await fetchFileBytesCompressed('openos.ntar.gz'));
+    computer.add_overlay(await getOpenosImage());
     screen.addRunOverlay(computer);
     return computer;
 }
+
+// called with the storage contents of each computer in a snapshot that could not be restored,
+// e.g. one from an older build, and gives every computer its EEPROMs, overlays and drives back
+window.nwRestoreStorage = async (machines) => {
+    const parent = document.getElementById('container');
+    for (const contents of machines) {
+        const computer = new window.nwComputer();
+        const screen = new window.nwScreen(computer, parent, 1, 80, 25);
+        for (const entry of contents) {
+            switch (entry.kind) {
+                case 'eeprom':
+                    computer.add_eeprom(entry.code, entry.label, entry.readOnly, null, entry.data);
+                    break;
+                case 'overlay': {
+                    const image = new window.nwBaseImage(entry.archive);
+                    computer.add_overlay(image);
+                    image.free();
+                    break;
+                }
+                case 'drive':
+                    computer.add_drive(entry.image, entry.capacity, entry.sectorSize, entry.platters, entry.speed);
+                    break;
+            }
+        }
+        screen.addRunOverlay(computer);
+    }
+};
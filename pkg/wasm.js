@@ -62,32 +62,191 @@ ch_to_oc_map["End"] = 0xCF;
 ch_to_oc_map["PageUp"] = 0xC9;
 ch_to_oc_map["PageDown"] = 0xD1;
 
+// computers that are ticking, and every computer ever created
 let computers = [];
+let allComputers = [];
+
+function readCString(ptr) {
+    const wasmMemory = new Uint8Array(wasm.memory.buffer);
+    let end = ptr;
+    while (wasmMemory[end] !== 0) end++;
+    return new TextDecoder("utf-8").decode(wasmMemory.subarray(ptr, end));
+}
+
+// loaders return -1 (or a null pointer) and leave the reason in last_error
+function checkStatus(result) {
+    if (result < 0) {
+        throw new Error(readCString(wasm.last_error()));
+    }
+    return result;
+}
+function checkPtr(ptr) {
+    if (ptr === 0) {
+        throw new Error(readCString(wasm.last_error()));
+    }
+    return ptr;
+}
+
+// copies bytes into a block from alloc_block for the duration of f(ptr, len), since loaders only
+// borrow their buffers. null or empty bytes are passed as a null pointer
+function withBlock(bytes, f) {
+    if (bytes == null || bytes.byteLength === 0) {
+        return f(0, 0);
+    }
+    const ptr = checkPtr(wasm.alloc_block(bytes.byteLength));
+    new Uint8Array(wasm.memory.buffer, ptr, bytes.byteLength).set(bytes);
+    try {
+        return f(ptr, bytes.byteLength);
+    } finally {
+        wasm.free_block(ptr);
+    }
+}
 
 export class Computer {
-    constructor() {
-        this.ptr = wasm.new_computer();
+    // ptr is only given when restoring a snapshot
+    constructor(tmpSize = 64 * 1024, ptr = null) {
+        this.ptr = ptr ?? checkPtr(wasm.new_computer(tmpSize));
         this.screens = [];
+        // EEPROMs, overlays and drives, whose contents are saved with every snapshot
+        this.storage = [];
+        allComputers.push(this);
+        snapshotDirty = true;
     }
     start_ticking() {
         computers.push(this);
     }
-    add_eeprom(bytes) {
-        let code = wasm.alloc_block(bytes.byteLength);
-        let data = wasm.alloc_block(1024);
-        const wasmMemory = new Uint8Array(wasm.memory.buffer, code, bytes.byteLength);
-        wasmMemory.set(bytes);
-        wasm.load_eeprom(this.ptr, code, bytes.byteLength, bytes.byteLength, data, 1024, 0);
+    // 1 to 3, limits how many direct component calls run per tick
+    set_cpu_tier(tier) {
+        checkStatus(wasm.set_cpu_tier(this.ptr, tier));
+    }
+    // Lua instructions per tick, and without yielding before "too long without yielding"
+    set_instruction_limits(perTick, timeout) {
+        checkStatus(wasm.set_instruction_limits(this.ptr, perTick, BigInt(timeout)));
+    }
+    // onChange, if given, is called with no arguments whenever user code writes to the EEPROM
+    add_eeprom(bytes, label = "EEPROM", readOnly = false, onChange = null, data = null) {
+        const labelBytes = new TextEncoder().encode(label);
+        let notify = -1;
+        if (onChange != null) {
+            notify = eepromListeners.length;
+            eepromListeners.push(onChange);
+        }
+        const codeSize = Math.max(4096, bytes.byteLength);
+        const dataLen = data == null ? 0 : data.byteLength;
+        const eeprom = withBlock(bytes, (code, codeLen) =>
+            withBlock(data, (dataPtr) =>
+                withBlock(labelBytes, (labelPtr, labelLen) =>
+                    checkPtr(wasm.load_eeprom(this.ptr, code, codeSize, codeLen, dataPtr, 1024, dataLen, labelPtr, labelLen, readOnly, notify)))));
+        this.storage.push({ kind: "eeprom", ptr: eeprom });
+        return eeprom;
+    }
+    // the current contents, to persist and pass back to add_eeprom later
+    eeprom_contents(eeprom) {
+        const copy = (ptr, len) => new Uint8Array(wasm.memory.buffer, ptr, len).slice();
+        return {
+            code: copy(wasm.eeprom_code(eeprom), wasm.eeprom_code_size(eeprom)),
+            data: copy(wasm.eeprom_data(eeprom), wasm.eeprom_data_size(eeprom)),
+            label: readString(wasm.eeprom_label(eeprom), wasm.eeprom_label_size(eeprom)),
+            readOnly: wasm.eeprom_read_only(eeprom),
+        };
     }
     add_vfs(bytes) {
-        let alloc = wasm.alloc_block(bytes.byteLength);
-        const wasmOpenos = new Uint8Array(wasm.memory.buffer, alloc, bytes.byteLength);
-        wasmOpenos.set(bytes);
-        wasm.load_vfs(this.ptr, alloc, bytes.byteLength);
+        withBlock(bytes, (ptr, len) => checkStatus(wasm.load_vfs(this.ptr, ptr, len)));
+    }
+    add_vfs_section(bytes, section, label = "") {
+        const labelBytes = new TextEncoder().encode(label);
+        withBlock(bytes, (ptr, len) =>
+            withBlock(labelBytes, (labelPtr, labelLen) =>
+                checkStatus(wasm.load_vfs_section(this.ptr, ptr, len, section, labelPtr, labelLen))));
+    }
+    add_vfs_sections(bytes) {
+        return withBlock(bytes, (ptr, len) => checkStatus(wasm.load_vfs_sections(this.ptr, ptr, len)));
+    }
+    add_overlay(image) {
+        const overlay = checkPtr(wasm.mount_overlay(this.ptr, image.ptr));
+        this.storage.push({ kind: "overlay", ptr: overlay });
+        return overlay;
+    }
+    // backend implements the methods called from hostFsImports, see there
+    add_host_fs(backend, capacity, readOnly = false) {
+        const disk = hostDisks.length;
+        hostDisks.push(backend);
+        checkStatus(wasm.load_host_fs(this.ptr, disk, capacity, readOnly));
+        return disk;
+    }
+    add_drive(bytes, capacity, sectorSize = 512, platters = 1, speed = 1) {
+        const drive = withBlock(bytes, (ptr, len) =>
+            checkPtr(wasm.load_drive(this.ptr, ptr, len, capacity, sectorSize, platters, speed)));
+        this.storage.push({ kind: "drive", ptr: drive, capacity, sectorSize, platters, speed });
+        return drive;
+    }
+    drive_image(drive) {
+        return new Uint8Array(wasm.memory.buffer, wasm.drive_data(drive), wasm.drive_size(drive)).slice();
+    }
+    reset_overlay(overlay) {
+        wasm.reset_overlay(overlay);
+    }
+    // a ustar archive of the overlay's contents, loadable again with add_vfs
+    export_overlay(overlay) {
+        const exported = wasm.export_overlay(overlay);
+        const bytes = new Uint8Array(wasm.memory.buffer, wasm.export_data(exported), wasm.export_size(exported)).slice();
+        wasm.free_export(exported);
+        return bytes;
+    }
+    // the contents of every EEPROM, overlay and drive, which outlive a module build unlike the
+    // snapshot memory; a page can add them to a fresh computer to get its files back
+    storage_contents() {
+        return this.storage.map((entry) => {
+            switch (entry.kind) {
+                case "eeprom": return { kind: entry.kind, ...this.eeprom_contents(entry.ptr) };
+                case "overlay": return { kind: entry.kind, archive: this.export_overlay(entry.ptr) };
+                case "drive": {
+                    const { ptr, ...params } = entry;
+                    return { ...params, image: this.drive_image(ptr) };
+                }
+            }
+        });
+    }
+    add_disk_drive() {
+        return checkPtr(wasm.new_disk_drive(this.ptr));
+    }
+    insert_floppy(drive, bytes) {
+        withBlock(bytes, (ptr, len) => checkStatus(wasm.insert_floppy(drive, ptr, len)));
+    }
+    eject_floppy(drive) {
+        return checkStatus(wasm.eject_floppy(drive)) === 1;
+    }
+    // recordings need a page opened with ?seed=N and must start before the computer first ticks;
+    // replay against a computer set up the same way, also before its first tick
+    start_recording() {
+        checkStatus(wasm.start_recording(this.ptr));
+    }
+    stop_recording() {
+        const log = checkPtr(wasm.stop_recording(this.ptr));
+        const bytes = new Uint8Array(wasm.memory.buffer, wasm.export_data(log), wasm.export_size(log)).slice();
+        wasm.free_export(log);
+        return bytes;
+    }
+    start_replay(bytes) {
+        withBlock(bytes, (ptr, len) => checkStatus(wasm.start_replay(this.ptr, ptr, len)));
+    }
+    is_replaying() {
+        return wasm.is_replaying(this.ptr);
     }
 }
 window.nwComputer = Computer;
 
+export class BaseImage {
+    constructor(bytes) {
+        this.ptr = withBlock(bytes, (ptr, len) => checkPtr(wasm.load_base_image(ptr, len)));
+    }
+    free() {
+        wasm.free_base_image(this.ptr);
+        this.ptr = 0;
+    }
+}
+window.nwBaseImage = BaseImage;
+
 function createScreenElement(element, width, height) {
     const wrapper = document.createElement("div");
     wrapper.classList.add("screen-wrapper");
@@ -120,8 +279,9 @@ function createScreenElement(element, width, height) {
 let screens = [];
 
 class Screen {
-    constructor(computer, parent, addKeyboard, width, height) {
-        this.ptr = wasm.new_screen(computer.ptr, addKeyboard, width, height);
+    // ptr is only given when restoring a snapshot
+    constructor(computer, parent, addKeyboard, width, height, ptr = null) {
+        this.ptr = ptr ?? wasm.new_screen(computer.ptr, addKeyboard, width, height);
         this.inner = createScreenElement(parent, width, height);
         let element = this.inner.element;
         element.onkeydown = function(e) {
@@ -179,8 +339,16 @@ function wasmSetCell(id, x, y, val, fg, bg) {
 
 function tickComputer() {
     try {
-        for (const x in computers) {
-            wasm.tick(computers[x].ptr);
+        for (const computer of computers.slice()) {
+            hostTime = wasm.current_time();
+            snapshotDirty = true;
+            if (!wasm.tick(computer.ptr)) {
+                // it stopped on an error, see the console; running it again reboots it
+                computers.splice(computers.indexOf(computer), 1);
+                for (const screen of computer.screens) {
+                    screen.addRunOverlay(computer);
+                }
+            }
         }
         for (const x in screens) {
             wasm.update_screen(screens[x].ptr, x, screens[x].inner.width, screens[x].inner.height);
@@ -195,9 +363,174 @@ function tickComputer() {
     }
 }
 
+// Whole-machine snapshots. Everything a computer has (Lua heap and coroutines, signal queue, screen
+// buffers, GPU VRAM, filesystems) lives in the module's linear memory, so a copy of it taken between
+// ticks captures every machine at once. A snapshot only fits the exact module build it came from.
+// It can't be taken per computer: lua53 has no Eris-style persistence, and every computer's Lua heap
+// shares the one allocator, so only the whole memory holds a consistent state.
+// Host objects can't be saved: backends from add_host_fs and EEPROM listeners are passed to
+// restoreSnapshot again, in the order they were first added.
+// Each computer's storage contents are saved next to the memory, so a snapshot from an older
+// build still gives its files back through window.nwRestoreStorage.
+export function saveSnapshot() {
+    return {
+        build: moduleBuild,
+        time: wasm.current_time(),
+        hostDisks: hostDisks.length,
+        eepromListeners: eepromListeners.length,
+        memory: new Uint8Array(wasm.memory.buffer).slice(),
+        computers: allComputers.map((computer) => ({
+            ptr: computer.ptr,
+            ticking: computers.includes(computer),
+            storage: computer.storage,
+            contents: computer.storage_contents(),
+            screens: computer.screens.map((screen) => ({
+                ptr: screen.ptr,
+                width: screen.inner.width,
+                height: screen.inner.height,
+            })),
+        })),
+    };
+}
+
+// must be called instead of init, before any computer is created. host holds the hostDisks and
+// eepromListeners arrays; every host disk the snapshot used must be there again, as the restored
+// filesystems call into them by index. Missing listeners are not told about EEPROM writes
+function restoreSnapshot(snapshot, parent, host = {}) {
+    if (snapshot.build !== moduleBuild) {
+        throw new Error("snapshot was taken with a different module build");
+    }
+    const disks = host.hostDisks ?? [];
+    if (disks.length < snapshot.hostDisks) {
+        throw new Error(`snapshot needs ${snapshot.hostDisks} host disks, got ${disks.length}`);
+    }
+    const listeners = host.eepromListeners ?? [];
+    while (listeners.length < snapshot.eepromListeners) {
+        listeners.push(() => {});
+    }
+
+    const pages = snapshot.memory.byteLength / 65536 - wasm.memory.buffer.byteLength / 65536;
+    if (pages > 0) {
+        wasm.memory.grow(pages);
+    }
+    new Uint8Array(wasm.memory.buffer).set(snapshot.memory);
+    wasm.resume_clock(snapshot.time);
+    hostTime = wasm.current_time();
+    hostDisks = disks;
+    eepromListeners = listeners;
+    for (const saved of snapshot.computers) {
+        const computer = new Computer(0, saved.ptr);
+        computer.storage = saved.storage ?? [];
+        for (const screen of saved.screens) {
+            new Screen(computer, parent, false, screen.width, screen.height, screen.ptr);
+        }
+        if (saved.ticking) {
+            computer.start_ticking();
+        } else {
+            for (const screen of computer.screens) {
+                screen.addRunOverlay(computer);
+            }
+        }
+    }
+    snapshotDirty = false;
+}
+window.nwSaveSnapshot = saveSnapshot;
+
+function openSnapshotDb() {
+    return new Promise((resolve, reject) => {
+        const request = indexedDB.open("neoweb", 1);
+        request.onupgradeneeded = () => request.result.createObjectStore("snapshots");
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+}
+// resolves once the snapshot is committed
+async function storeSnapshot(snapshot) {
+    const db = await openSnapshotDb();
+    return new Promise((resolve, reject) => {
+        const transaction = db.transaction("snapshots", "readwrite");
+        transaction.objectStore("snapshots").put(snapshot, "autosave");
+        transaction.oncomplete = () => resolve();
+        transaction.onerror = () => reject(transaction.error);
+        transaction.onabort = () => reject(transaction.error);
+    });
+}
+async function loadStoredSnapshot() {
+    const db = await openSnapshotDb();
+    return new Promise((resolve, reject) => {
+        const request = db.transaction("snapshots").objectStore("snapshots").get("autosave");
+        request.onsuccess = () => resolve(request.result ?? null);
+        request.onerror = () => reject(request.error);
+    });
+}
+window.nwClearSnapshot = async () => {
+    const db = await openSnapshotDb();
+    db.transaction("snapshots", "readwrite").objectStore("snapshots").delete("autosave");
+};
+
+// ?seed=N gives a reproducible run, which neither resumes nor overwrites the saved snapshot
+const deterministicSeed = new URLSearchParams(location.search).get("seed");
+
+// set by every tick and new computer, so an unchanged machine is not copied again
+let snapshotDirty = false;
+let savingSnapshot = false;
+
+async function autosave() {
+    if (!snapshotDirty || savingSnapshot || allComputers.length === 0 || deterministicSeed != null) {
+        return;
+    }
+    savingSnapshot = true;
+    snapshotDirty = false;
+    try {
+        await storeSnapshot(saveSnapshot());
+    } catch (e) {
+        snapshotDirty = true;
+        console.warn("could not save a snapshot", e);
+    } finally {
+        savingSnapshot = false;
+    }
+}
+
+// saved whenever the tab is hidden, which is the last reliable point before it is closed. Browsers
+// may kill the page before that write commits, so it is also saved every minute while running
+document.addEventListener("visibilitychange", () => {
+    if (document.visibilityState === "hidden") {
+        autosave();
+    }
+});
+setInterval(autosave, 60 * 1000);
+
 async function runComputer() {
     try {
-        wasm.init();
+        let snapshot = null;
+        try {
+            snapshot = await loadStoredSnapshot();
+        } catch (e) {
+            console.warn("could not read the saved snapshot", e);
+        }
+        let restored = false;
+        if (deterministicSeed == null && snapshot != null) {
+            try {
+                // the page hands back the host backends the snapshot was taken with
+                const host = (await window.nwRestoreHost?.(snapshot)) ?? {};
+                restoreSnapshot(snapshot, document.getElementById("container"), host);
+                restored = true;
+            } catch (e) {
+                console.warn("could not restore the saved snapshot", e);
+            }
+        }
+        if (!restored) {
+            wasm.init();
+            if (deterministicSeed != null) {
+                // one tick is 1/20 of a second, like in OC
+                checkStatus(wasm.enable_deterministic(BigInt.asUintN(64, BigInt(deterministicSeed)), 0, 0.05));
+            }
+            hostTime = wasm.current_time();
+            if (deterministicSeed == null && snapshot?.computers != null) {
+                // each entry is what storage_contents returned, for the page to put on new computers
+                await window.nwRestoreStorage?.(snapshot.computers.map((saved) => saved.contents ?? []));
+            }
+        }
 
         requestAnimationFrame(tickComputer);
     } catch(e) {
@@ -210,7 +543,68 @@ async function runComputer() {
     }
 }
 
+// Filesystems stored by the page, e.g. in IndexedDB or through the File System Access API.
+// All calls are synchronous, so backends must serve them from memory.
+let hostDisks = [];
+let eepromListeners = [];
+
+function readString(ptr, len) {
+    return new TextDecoder("utf-8").decode(new Uint8Array(wasm.memory.buffer, ptr, len));
+}
+
+// open returns a file descriptor of 0 or more, or -1 on failure; the shim in hostfs.rs shifts it
+// by one for neonucleus, so backends need not avoid 0
+const hostFsImports = {
+    open: (disk, path, pathLen, mode, modeLen) => hostDisks[disk].open(readString(path, pathLen), readString(mode, modeLen)),
+    close: (disk, fd) => hostDisks[disk].close(fd),
+    read: (disk, fd, buf, len) => {
+        // returns a Uint8Array of at most len bytes, or null on error
+        const data = hostDisks[disk].read(fd, len);
+        if (data == null) return -1;
+        // a backend returning more than asked for must not write past buf
+        const count = Math.min(data.byteLength, len);
+        new Uint8Array(wasm.memory.buffer, buf, count).set(data.subarray(0, count));
+        return count;
+    },
+    write: (disk, fd, buf, len) => hostDisks[disk].write(fd, new Uint8Array(wasm.memory.buffer, buf, len).slice()),
+    seek: (disk, fd, whence, offset) => hostDisks[disk].seek(fd, ["set", "cur", "end"][whence], offset),
+    list: (disk, path, pathLen, buf, len) => {
+        // returns an array of names with directories ending in "/", or null if path isn't a directory
+        const names = hostDisks[disk].list(readString(path, pathLen));
+        if (names == null) return -1;
+        const bytes = new TextEncoder().encode(names.join("\0"));
+        if (bytes.byteLength <= len) {
+            new Uint8Array(wasm.memory.buffer, buf, bytes.byteLength).set(bytes);
+        }
+        return bytes.byteLength;
+    },
+    stat: (disk, path, pathLen) => hostDisks[disk].stat(readString(path, pathLen)),
+    size: (disk, path, pathLen) => hostDisks[disk].size(readString(path, pathLen)),
+    last_modified: (disk, path, pathLen) => hostDisks[disk].lastModified(readString(path, pathLen)),
+    make_directory: (disk, path, pathLen) => hostDisks[disk].makeDirectory(readString(path, pathLen)),
+    remove: (disk, path, pathLen) => hostDisks[disk].remove(readString(path, pathLen)),
+    rename: (disk, from, fromLen, to, toLen) => hostDisks[disk].rename(readString(from, fromLen), readString(to, toLen)),
+    space_used: (disk) => hostDisks[disk].spaceUsed(),
+    // getLabel returns a string and setLabel stores one with the disk. Both are optional, in which
+    // case the disk has no label and setting one fails
+    get_label: (disk, buf, len) => {
+        const bytes = new TextEncoder().encode(hostDisks[disk].getLabel?.() ?? "");
+        const count = Math.min(bytes.byteLength, len);
+        new Uint8Array(wasm.memory.buffer, buf, count).set(bytes.subarray(0, count));
+        return count;
+    },
+    set_label: (disk, buf, len) => {
+        if (hostDisks[disk].setLabel == null) return -1;
+        hostDisks[disk].setLabel(readString(buf, len));
+        return 0;
+    },
+};
+
 const importObject = {
+    neoweb_fs: hostFsImports,
+    neoweb_eeprom: {
+        changed: (id) => eepromListeners[id](),
+    },
     neoweb_console: {
         set_cell: wasmSetCell,
     },
@@ -219,25 +613,16 @@ const importObject = {
             return Date.now() / 1000;
         },
         debug_log: (ptr) => {
-            const wasmMemory = new Uint8Array(wasm.memory.buffer);
-            let end = ptr;
-            while (wasmMemory[end] !== 0) end++;
-            const slice = wasmMemory.subarray(ptr, end);
-            const str = new TextDecoder("utf-8").decode(slice);
-            console.log('[wasm]:', str);
+            console.log('[wasm]:', readCString(ptr));
         },
         debug_error: (ptr) => {
-            const wasmMemory = new Uint8Array(wasm.memory.buffer);
-            let end = ptr;
-            while (wasmMemory[end] !== 0) end++;
-            const slice = wasmMemory.subarray(ptr, end);
-            const str = new TextDecoder("utf-8").decode(slice);
-            console.error('[wasm]:', str);
+            console.error('[wasm]:', readCString(ptr));
         }
     },
     libc: {
+        // read from hostTime rather than calling back into the module from an import
         get_unix_time_s: () => {
-            return BigInt(Math.floor(Date.now() / 1000));
+            return BigInt(Math.floor(hostTime));
         }
     },
     env: {
@@ -254,14 +639,26 @@ const importObject = {
     }
 };
 let wasm = undefined;
+// the module's clock as of the last call into it, set before every tick
+let hostTime = 0;
 
 const response = await fetch("neoweb.wasm.gz");
 const decompressedStream = response.body.pipeThrough(new DecompressionStream("gzip"));
 
 const buffer = await new Response(decompressedStream).arrayBuffer();
+const moduleBuild = Array.from(new Uint8Array(await crypto.subtle.digest("SHA-256", buffer)))
+    .map((b) => b.toString(16).padStart(2, "0"))
+    .join("");
 
 const result = await WebAssembly.instantiate(buffer, importObject);
 
 wasm = result.instance.exports;
+// a component with fields and value objects, for checking the component bridge from Lua.
+// Only builds with the probe feature, like those from build_debug, export it
+if (wasm.add_probe != null) {
+    Computer.prototype.add_probe = function() {
+        checkStatus(wasm.add_probe(this.ptr));
+    };
+}
 
 await runComputer();
\ No newline at end of file
@@ -1,34 +1,46 @@
 use std::{
-    ffi::{c_char, c_void},
+    ffi::{CStr, c_char, c_void},
     ptr::{null, null_mut},
 };
 
 use lua53_sys::{
-    LUA_OK, LUA_REGISTRYINDEX, LUA_TBOOLEAN, LUA_TNUMBER, LUA_TSTRING, LUA_YIELD, lua_State,
-    lua_checkstack, lua_close, lua_createtable, lua_getfield, lua_gettop, lua_isinteger,
-    lua_isnumber, lua_newstate, lua_pushboolean, lua_pushcclosure, lua_pushinteger,
-    lua_pushlightuserdata, lua_pushlstring, lua_pushnil, lua_pushnumber, lua_pushstring,
-    lua_resume, lua_setfield, lua_setglobal, lua_seti, lua_settable, lua_settop, lua_toboolean,
-    lua_tointegerx, lua_tolstring, lua_tonumberx, lua_touserdata, lua_type, luaL_argerror,
-    luaL_checkinteger, luaL_checklstring, luaL_checknumber, luaL_error, luaL_loadbufferx,
-    luaL_openlibs,
+    LUA_MASKCOUNT, LUA_OK, LUA_REGISTRYINDEX, LUA_TBOOLEAN, LUA_TNUMBER, LUA_TSTRING, LUA_TTABLE,
+    LUA_TUSERDATA, LUA_YIELD, lua_Debug, lua_State, lua_absindex, lua_checkstack, lua_close,
+    lua_createtable, lua_getfield, lua_getglobal, lua_gettop, lua_isinteger, lua_isnumber,
+    lua_isyieldable, lua_newstate, lua_newuserdata, lua_next, lua_pushboolean, lua_pushcclosure,
+    lua_pushinteger, lua_pushlightuserdata, lua_pushlstring, lua_pushnil, lua_pushnumber,
+    lua_pushstring, lua_pushvalue, lua_rawequal, lua_rawgeti, lua_rawlen, lua_resume, lua_setfield,
+    lua_setglobal, lua_sethook, lua_seti, lua_settable, lua_settop, lua_toboolean, lua_tointegerx,
+    lua_tolstring, lua_tonumberx, lua_topointer, lua_touserdata, lua_type,
+    lua_yieldk, luaL_argerror, luaL_checkinteger, luaL_checklstring, luaL_checknumber,
+    luaL_checkudata, luaL_error, luaL_loadbufferx, luaL_newmetatable, luaL_openlibs,
+    luaL_setmetatable, luaL_testudata,
 };
 use neonucleus::ffi::{
     NN_MAX_ARGS, NN_STATE_BLACKOUT, NN_STATE_BUSY, NN_STATE_CLOSING, NN_STATE_REPEAT,
     NN_STATE_RUNNING, NN_STATE_SETUP, NN_STATE_SWITCH, NN_VALUE_ARRAY, NN_VALUE_BOOL,
     NN_VALUE_CSTR, NN_VALUE_INT, NN_VALUE_NIL, NN_VALUE_NUMBER, NN_VALUE_STR, NN_VALUE_TABLE,
-    nn_Alloc, nn_addArgument, nn_addHeat, nn_alloc, nn_architecture, nn_clearError, nn_computer,
-    nn_dealloc, nn_deallocStr, nn_fetchSignalValue, nn_findComponent, nn_getAllocator,
-    nn_getArchitecture, nn_getComponentAddress, nn_getComponentSlot, nn_getComponentTable,
-    nn_getComponentType, nn_getComputerAddress, nn_getComputerMemoryTotal, nn_getEnergy,
-    nn_getError, nn_getMaxEnergy, nn_getReturn, nn_getReturnCount, nn_getState,
-    nn_getSupportedArchitecture, nn_getTableMethod, nn_getTemperature, nn_getTmpAddress,
-    nn_getUniverse, nn_getUptime, nn_indexUser, nn_invokeComponentMethod, nn_isMethodEnabled,
-    nn_isOverheating, nn_isOverworked, nn_iterComponent, nn_methodDoc, nn_popSignal, nn_pushSignal,
-    nn_resetCall, nn_resize, nn_setCError, nn_setError, nn_setNextArchitecture, nn_setState,
-    nn_signalSize, nn_strcmp, nn_unicode_char, nn_unicode_indexPermissive,
-    nn_unicode_lenPermissive, nn_value, nn_values_boolean, nn_values_dropAll, nn_values_getType,
-    nn_values_integer, nn_values_nil, nn_values_number, nn_values_string,
+    nn_Alloc, nn_addArgument, nn_addHeat, nn_alloc, nn_architecture, nn_clearError,
+    nn_componentTable, nn_computer, nn_dealloc, nn_deallocStr, nn_fetchSignalValue,
+    nn_findComponent, nn_getAllocator, nn_getArchitecture, nn_getComponentAddress,
+    nn_getComponentSlot, nn_getComponentTable, nn_getComponentType, nn_getComputerAddress,
+    nn_getComputerMemoryTotal, nn_getEnergy, nn_getError, nn_getMaxEnergy, nn_getReturn,
+    nn_getReturnCount, nn_getState, nn_getSupportedArchitecture, nn_getTableMethod,
+    nn_getTemperature, nn_getTmpAddress, nn_getUniverse, nn_getUptime, nn_indexUser,
+    nn_invokeComponentMethod, nn_isMethodEnabled, nn_isOverheating, nn_isOverworked,
+    nn_iterComponent, nn_methodDoc, nn_popSignal, nn_pushSignal, nn_resetCall, nn_resize,
+    nn_setCError, nn_setError, nn_setNextArchitecture, nn_setState, nn_signalSize, nn_strcmp,
+    nn_unicode_char, nn_unicode_indexPermissive, nn_unicode_lenPermissive, nn_value,
+    nn_values_array, nn_values_boolean, nn_values_drop, nn_values_dropAll, nn_values_getType,
+    nn_values_integer, nn_values_nil, nn_values_number, nn_values_set, nn_values_setPair,
+    nn_values_string, nn_values_table,
+};
+
+use crate::{
+    context::{Random, next_random},
+    devices::device_info,
+    fields::{fields, is_field},
+    value::{self, ValueHandle, Values},
 };
 
 pub const ARCH_TABLE: nn_architecture = nn_architecture {
@@ -38,16 +50,41 @@ pub const ARCH_TABLE: nn_architecture = nn_architecture {
     teardown: Some(teardown),
     getMemoryUsage: Some(get_memory_usage),
     tick: Some(tick),
+    // lua53 has no Eris-style persistence; the host snapshots the whole linear memory instead,
+    // see saveSnapshot in pkg/wasm.js
     serialize: None,
     deserialize: None,
 };
 const LUA_SANDBOX: &[u8] = include_bytes!("sandbox.lua");
+/// Budget a direct call uses up, so a tier 3 CPU makes 384 of them per tick.
+const DIRECT_CALL_COST: f64 = 1.0 / 256.0;
+/// Instructions between calls of the watchdog hook.
+const HOOK_STEP: i32 = 1000;
+/// Registry name of the metatable shared by every value object.
+const VALUE_METATABLE: &CStr = c"neoweb.value";
 
 #[repr(C)]
 struct State {
     lua: *mut lua_State,
     computer: *mut nn_computer,
     mem_usage: usize,
+    /// What is left of this tick's direct-call budget.
+    call_budget: f64,
+    /// Set by an indirect call or an exhausted budget, so invoke yields before returning.
+    must_yield: bool,
+    /// Instructions left this tick before the watchdog preempts the machine.
+    tick_instructions: i64,
+    /// Instructions run since the machine last yielded to the host on its own.
+    run_instructions: u64,
+    /// Set once run_instructions passes the timeout. The machine errors from then on.
+    timed_out: bool,
+    /// Whether the watchdog, rather than the machine, ended the last resume. The sandbox's
+    /// resume passes such yields on, so every coroutine between it and the host yields too.
+    preempted: bool,
+    /// Uptime at which running code next heats the computer up.
+    next_heat: f64,
+    /// math.random's generator, so math.randomseed only affects this machine.
+    random: Random,
 }
 
 unsafe extern "C" fn lua_alloc(
@@ -82,26 +119,141 @@ unsafe fn get_alloc(lua: *mut lua_State) -> *mut nn_Alloc {
     unsafe { lua_pop(lua, 1) };
     unsafe { nn_getAllocator(nn_getUniverse((*state).computer)) }
 }
-unsafe fn get_value(lua: *mut lua_State, idx: i32) -> nn_value {
+/// Tables nested deeper than this are rejected rather than converted.
+const MAX_VALUE_DEPTH: usize = 16;
+/// Total number of array elements and table pairs a single argument may hold.
+const MAX_VALUE_ENTRIES: usize = 4096;
+
+/// Converts the Lua value at idx. Sequences become arrays, other tables become tables; functions,
+/// threads and userdata other than value objects are rejected.
+unsafe fn get_value(lua: *mut lua_State, idx: i32) -> Result<nn_value, &'static CStr> {
+    let idx = unsafe { lua_absindex(lua, idx) };
+    let mut seen = Vec::new();
+    let mut entries = 0;
+    unsafe { convert_value(lua, idx, &mut seen, &mut entries) }
+}
+unsafe fn convert_value(
+    lua: *mut lua_State,
+    idx: i32,
+    seen: &mut Vec<*const c_void>,
+    entries: &mut usize,
+) -> Result<nn_value, &'static CStr> {
     let ty = unsafe { lua_type(lua, idx) };
     let alloc = unsafe { get_alloc(lua) };
 
     if ty == LUA_TBOOLEAN {
-        unsafe { nn_values_boolean(lua_toboolean(lua, idx) != 0) }
-    } else if unsafe { lua_type(lua, idx) } <= 0 {
-        unsafe { nn_values_nil() }
+        Ok(unsafe { nn_values_boolean(lua_toboolean(lua, idx) != 0) })
+    } else if ty <= 0 {
+        Ok(unsafe { nn_values_nil() })
     } else if ty == LUA_TSTRING {
         let mut len = 0;
         let s = unsafe { lua_tolstring(lua, idx, &raw mut len) };
-        unsafe { nn_values_string(alloc, s, len) }
+        Ok(unsafe { nn_values_string(alloc, s, len) })
     } else if ty == LUA_TNUMBER && unsafe { lua_isinteger(lua, idx) } != 0 {
-        unsafe { nn_values_integer(lua_tointegerx(lua, idx, null_mut())) }
-    } else if ty == LUA_TNUMBER && unsafe { lua_isnumber(lua, idx) } != 0 {
-        unsafe { nn_values_number(lua_tonumberx(lua, idx, null_mut())) }
+        Ok(unsafe { nn_values_integer(lua_tointegerx(lua, idx, null_mut())) })
+    } else if ty == LUA_TNUMBER {
+        Ok(unsafe { nn_values_number(lua_tonumberx(lua, idx, null_mut())) })
+    } else if ty == LUA_TUSERDATA {
+        let handle = unsafe { luaL_testudata(lua, idx, VALUE_METATABLE.as_ptr()) };
+        if handle.is_null() {
+            return Err(c"unsupported type");
+        }
+        let handle = unsafe { *handle.cast::<ValueHandle>() };
+        let values = unsafe { values(lua) };
+        if values.tag(handle).is_none() {
+            return Err(c"value object is gone");
+        }
+        Ok(unsafe { value::to_value(values, handle) })
+    } else if ty == LUA_TTABLE {
+        let ptr = unsafe { lua_topointer(lua, idx) };
+        if seen.contains(&ptr) {
+            return Err(c"recursive table");
+        }
+        if seen.len() >= MAX_VALUE_DEPTH || unsafe { lua_checkstack(lua, 3) } == 0 {
+            return Err(c"table is nested too deeply");
+        }
+        seen.push(ptr);
+        let value = unsafe { convert_table(lua, idx, alloc, seen, entries) };
+        seen.pop();
+        value
     } else {
-        unsafe { nn_values_nil() }
+        Err(c"unsupported type")
     }
 }
+unsafe fn convert_table(
+    lua: *mut lua_State,
+    idx: i32,
+    alloc: *mut nn_Alloc,
+    seen: &mut Vec<*const c_void>,
+    entries: &mut usize,
+) -> Result<nn_value, &'static CStr> {
+    // count the pairs first, noting whether the keys are exactly 1..n
+    let border = unsafe { lua_rawlen(lua, idx) };
+    let mut len = 0;
+    let mut sequence = true;
+    unsafe { lua_pushnil(lua) };
+    while unsafe { lua_next(lua, idx) } != 0 {
+        len += 1;
+        if sequence {
+            let mut is_int = 0;
+            let key = unsafe { lua_tointegerx(lua, -2, &mut is_int) };
+            sequence = unsafe { lua_type(lua, -2) } == LUA_TNUMBER
+                && is_int != 0
+                && key >= 1
+                && key as usize <= border;
+        }
+        unsafe { lua_pop(lua, 1) };
+    }
+    sequence = sequence && len == border;
+    *entries += len;
+    if *entries > MAX_VALUE_ENTRIES {
+        return Err(c"table is too large");
+    }
+
+    if sequence {
+        let array = unsafe { nn_values_array(alloc, len) };
+        for i in 0..len {
+            unsafe { lua_rawgeti(lua, idx, (i + 1) as i64) };
+            let value = unsafe { convert_value(lua, lua_gettop(lua), seen, entries) };
+            unsafe { lua_pop(lua, 1) };
+            match value {
+                Ok(value) => unsafe { nn_values_set(array, i, value) },
+                Err(err) => {
+                    unsafe { nn_values_drop(array) };
+                    return Err(err);
+                }
+            }
+        }
+        return Ok(array);
+    }
+
+    let table = unsafe { nn_values_table(alloc, len) };
+    let mut i = 0;
+    unsafe { lua_pushnil(lua) };
+    while unsafe { lua_next(lua, idx) } != 0 {
+        let top = unsafe { lua_gettop(lua) };
+        let pair = unsafe { convert_value(lua, top - 1, seen, entries) }.and_then(|key| {
+            match unsafe { convert_value(lua, top, seen, entries) } {
+                Ok(val) => Ok((key, val)),
+                Err(err) => {
+                    unsafe { nn_values_drop(key) };
+                    Err(err)
+                }
+            }
+        });
+        unsafe { lua_pop(lua, 1) };
+        match pair {
+            Ok((key, val)) => unsafe { nn_values_setPair(table, i, key, val) },
+            Err(err) => {
+                unsafe { lua_pop(lua, 1) };
+                unsafe { nn_values_drop(table) };
+                return Err(err);
+            }
+        }
+        i += 1;
+    }
+    Ok(table)
+}
 unsafe fn push_value(lua: *mut lua_State, val: nn_value) {
     let t = unsafe { nn_values_getType(val) } as i32;
     match t {
@@ -127,9 +279,16 @@ unsafe fn push_value(lua: *mut lua_State, val: nn_value) {
             };
         }
         NN_VALUE_CSTR => {
-            unsafe { lua_pushstring(lua, val.__bindgen_anon_1.cstring) };
+            let cstring = unsafe { val.__bindgen_anon_1.cstring };
+            match unsafe { values(lua) }.lookup(cstring) {
+                Some(handle) => unsafe { push_value_object(lua, handle) },
+                None => unsafe { lua_pushstring(lua, cstring) },
+            }
         }
         NN_VALUE_ARRAY => {
+            if unsafe { lua_checkstack(lua, 2) } == 0 {
+                unsafe { luaL_error(lua, c"value is nested too deeply".as_ptr()) };
+            }
             let arr = unsafe { val.__bindgen_anon_1.array };
             let len = unsafe { *arr }.len;
             unsafe { lua_createtable(lua, len as i32, 0) };
@@ -140,6 +299,9 @@ unsafe fn push_value(lua: *mut lua_State, val: nn_value) {
             }
         }
         NN_VALUE_TABLE => {
+            if unsafe { lua_checkstack(lua, 3) } == 0 {
+                unsafe { luaL_error(lua, c"value is nested too deeply".as_ptr()) };
+            }
             let tbl = unsafe { val.__bindgen_anon_1.table };
             let len = unsafe { *tbl }.len;
             unsafe { lua_createtable(lua, 0, len as i32) };
@@ -156,6 +318,38 @@ unsafe fn push_value(lua: *mut lua_State, val: nn_value) {
     }
 }
 
+/// The value objects of the computer running lua.
+unsafe fn values<'a>(lua: *mut lua_State) -> &'a mut Values {
+    unsafe { crate::values((*get_state(lua)).computer) }
+}
+/// Pushes a userdata holding the handle. Only handles found with Values::lookup may be passed.
+unsafe fn push_value_object(lua: *mut lua_State, handle: ValueHandle) {
+    let userdata = unsafe { lua_newuserdata(lua, size_of::<ValueHandle>()) }.cast::<ValueHandle>();
+    unsafe { userdata.write(handle) };
+    unsafe { values(lua) }.retain(handle);
+    unsafe { luaL_setmetatable(lua, VALUE_METATABLE.as_ptr()) };
+}
+/// The handle in the value object at idx, or raises a Lua error if it is something else.
+unsafe fn check_value_object(lua: *mut lua_State, idx: i32) -> ValueHandle {
+    unsafe { *luaL_checkudata(lua, idx, VALUE_METATABLE.as_ptr()).cast::<ValueHandle>() }
+}
+/// Pushes the results of the last call and resets it. Value objects the call created but did
+/// not return are disposed of.
+unsafe fn push_returns(lua: *mut lua_State, computer: *mut nn_computer) -> i32 {
+    if !unsafe { nn_getError(computer).is_null() } {
+        unsafe { nn_resetCall(computer) };
+        unsafe { values(lua) }.sweep();
+        unsafe { luaL_error(lua, c"%s".as_ptr(), nn_getError(computer)) };
+    }
+    let retc = unsafe { nn_getReturnCount(computer) };
+    for i in 0..retc {
+        unsafe { push_value(lua, nn_getReturn(computer, i)) };
+    }
+    unsafe { nn_resetCall(computer) };
+    unsafe { values(lua) }.sweep();
+    retc as i32
+}
+
 unsafe fn pushlstring_safe(lua: *mut lua_State, s: *const i8, len: usize) -> *const c_char {
     if (unsafe { lua_checkstack(lua, 1) } == 0) {
         return null();
@@ -173,6 +367,9 @@ unsafe fn pushlstring_safe(lua: *mut lua_State, s: *const i8, len: usize) -> *co
 unsafe fn lua_pop(lua: *mut lua_State, n: i32) {
     unsafe { lua_settop(lua, -(n) - 1) }
 }
+fn lua_upvalueindex(i: i32) -> i32 {
+    LUA_REGISTRYINDEX - i
+}
 unsafe fn get_state(lua: *mut lua_State) -> *mut State {
     unsafe { lua_getfield(lua, LUA_REGISTRYINDEX, c"archPtr".as_ptr()) };
     let state = unsafe { lua_touserdata(lua, -1) };
@@ -189,6 +386,16 @@ unsafe extern "C" fn computer_uptime(lua: *mut lua_State) -> i32 {
     unsafe { lua_pushnumber(lua, nn_getUptime((*state).computer)) };
     1
 }
+unsafe extern "C" fn computer_is_timed_out(lua: *mut lua_State) -> i32 {
+    let state = unsafe { get_state(lua) };
+    unsafe { lua_pushboolean(lua, (*state).timed_out as i32) };
+    1
+}
+unsafe extern "C" fn computer_is_preempted(lua: *mut lua_State) -> i32 {
+    let state = unsafe { get_state(lua) };
+    unsafe { lua_pushboolean(lua, (*state).preempted as i32) };
+    1
+}
 unsafe extern "C" fn computer_is_overheating(lua: *mut lua_State) -> i32 {
     let state = unsafe { get_state(lua) };
     unsafe { lua_pushboolean(lua, nn_isOverheating((*state).computer) as i32) };
@@ -196,7 +403,8 @@ unsafe extern "C" fn computer_is_overheating(lua: *mut lua_State) -> i32 {
 }
 unsafe extern "C" fn computer_is_overworked(lua: *mut lua_State) -> i32 {
     let state = unsafe { get_state(lua) };
-    unsafe { lua_pushboolean(lua, nn_isOverworked((*state).computer) as i32) };
+    let overworked = unsafe { nn_isOverworked((*state).computer) || (*state).must_yield };
+    unsafe { lua_pushboolean(lua, overworked as i32) };
     1
 }
 unsafe extern "C" fn computer_get_state(lua: *mut lua_State) -> i32 {
@@ -241,7 +449,14 @@ unsafe extern "C" fn component_invoke(lua: *mut lua_State) -> i32 {
     }
     unsafe { nn_resetCall((*state).computer) };
     for i in 0..argc {
-        unsafe { nn_addArgument((*state).computer, get_value(lua, i + 3)) };
+        match unsafe { get_value(lua, i + 3) } {
+            Ok(value) => unsafe { nn_addArgument((*state).computer, value) },
+            Err(err) => {
+                unsafe { nn_resetCall((*state).computer) };
+                unsafe { luaL_argerror(lua, i + 3, err.as_ptr()) };
+                return 0;
+            }
+        }
     }
     if !unsafe { nn_invokeComponentMethod(component, method) } {
         unsafe { nn_resetCall((*state).computer) };
@@ -249,16 +464,87 @@ unsafe extern "C" fn component_invoke(lua: *mut lua_State) -> i32 {
         unsafe { lua_pushstring(lua, c"no such method".as_ptr()) };
         return 2;
     }
-    if !unsafe { nn_getError((*state).computer).is_null() } {
-        unsafe { nn_resetCall((*state).computer) };
-        unsafe { luaL_error(lua, c"%s".as_ptr(), nn_getError((*state).computer)) };
+    if unsafe { is_direct(nn_getComponentTable(component), method) } {
+        unsafe { (*state).call_budget -= DIRECT_CALL_COST };
+        unsafe { (*state).must_yield |= (*state).call_budget <= 0.0 };
+    } else {
+        // indirect calls take the rest of the tick, as they are synchronized in OC
+        unsafe { (*state).must_yield = true };
     }
-    let retc = unsafe { nn_getReturnCount((*state).computer) };
-    for i in 0..retc {
-        unsafe { push_value(lua, nn_getReturn((*state).computer, i)) };
+    unsafe { push_returns(lua, (*state).computer) }
+}
+
+unsafe fn is_direct(table: *mut nn_componentTable, method: *const c_char) -> bool {
+    let method = unsafe { CStr::from_ptr(method) };
+    let mut i = 0;
+    loop {
+        let mut direct = false;
+        let name = unsafe { nn_getTableMethod(table, i, &mut direct as *mut _) };
+        if name.is_null() {
+            return false;
+        }
+        if unsafe { CStr::from_ptr(name) } == method {
+            return direct;
+        }
+        i += 1;
+    }
+}
+
+unsafe extern "C" fn value_index(lua: *mut lua_State) -> i32 {
+    let handle = unsafe { check_value_object(lua, 1) };
+    if unsafe { lua_type(lua, 2) } != LUA_TSTRING {
+        unsafe { lua_pushnil(lua) };
+        return 1;
+    }
+    let name = unsafe { CStr::from_ptr(lua_tolstring(lua, 2, null_mut())) };
+    if unsafe { values(lua) }.method(handle, name).is_none() {
+        unsafe { lua_pushnil(lua) };
+        return 1;
     }
+    unsafe { lua_pushvalue(lua, 1) };
+    unsafe { lua_pushvalue(lua, 2) };
+    unsafe { lua_pushcclosure(lua, Some(value_call), 2) };
+    1
+}
+/// A method of a value object, bound to it. Works both as `value.method()` and `value:method()`.
+unsafe extern "C" fn value_call(lua: *mut lua_State) -> i32 {
+    let state = unsafe { get_state(lua) };
+    let handle = unsafe { check_value_object(lua, lua_upvalueindex(1)) };
+    let name = unsafe { CStr::from_ptr(lua_tolstring(lua, lua_upvalueindex(2), null_mut())) };
+    let values = unsafe { values(lua) };
+    let (Some(method), Some(userdata)) = (values.method(handle, name), values.userdata(handle)) else {
+        unsafe { luaL_error(lua, c"no such method".as_ptr()) };
+        return 0;
+    };
+    let first = if unsafe { lua_rawequal(lua, 1, lua_upvalueindex(1)) } != 0 {
+        2
+    } else {
+        1
+    };
     unsafe { nn_resetCall((*state).computer) };
-    retc as i32
+    for i in first..=unsafe { lua_gettop(lua) } {
+        match unsafe { get_value(lua, i) } {
+            Ok(value) => unsafe { nn_addArgument((*state).computer, value) },
+            Err(err) => {
+                unsafe { nn_resetCall((*state).computer) };
+                unsafe { luaL_argerror(lua, i, err.as_ptr()) };
+                return 0;
+            }
+        }
+    }
+    unsafe { method(userdata, (*state).computer) };
+    unsafe { push_returns(lua, (*state).computer) }
+}
+unsafe extern "C" fn value_gc(lua: *mut lua_State) -> i32 {
+    let handle = unsafe { check_value_object(lua, 1) };
+    unsafe { values(lua) }.release(handle);
+    0
+}
+unsafe extern "C" fn value_tostring(lua: *mut lua_State) -> i32 {
+    let handle = unsafe { check_value_object(lua, 1) };
+    let name = unsafe { values(lua) }.type_name(handle).unwrap_or(c"userdata");
+    unsafe { lua_pushstring(lua, name.as_ptr()) };
+    1
 }
 
 unsafe extern "C" fn computer_used_memory(lua: *mut lua_State) -> i32 {
@@ -329,19 +615,29 @@ unsafe extern "C" fn computer_get_architectures(lua: *mut lua_State) -> i32 {
     }
 }
 
+/// Returns false if the requested architecture is already running, like OC.
+/// Otherwise the computer switches to it at the end of this tick, see tick in lib.rs.
 unsafe extern "C" fn computer_set_architecture(lua: *mut lua_State) -> i32 {
     unsafe {
         let state = get_state(lua);
         let requested = luaL_checklstring(lua, 1, null_mut());
+        let current = nn_getArchitecture((*state).computer);
+        if nn_strcmp((*current).archName, requested) == 0 {
+            lua_pushboolean(lua, 0);
+            return 1;
+        }
+        let mut i = 0;
         loop {
-            let arch = nn_getArchitecture((*state).computer);
+            let arch = nn_getSupportedArchitecture((*state).computer, i);
             if arch.is_null() {
                 break;
             }
+            i += 1;
             if nn_strcmp((*arch).archName, requested) == 0 {
-                nn_setState((*state).computer, NN_STATE_SWITCH);
                 nn_setNextArchitecture((*state).computer, arch);
-                return 0;
+                nn_setState((*state).computer, NN_STATE_SWITCH);
+                lua_pushboolean(lua, 1);
+                return 1;
             }
         }
         luaL_error(lua, c"unsupported architecture: %s".as_ptr(), requested);
@@ -397,6 +693,41 @@ unsafe extern "C" fn computer_users(lua: *mut lua_State) -> i32 {
     }
 }
 
+/// Returns `{address = {class = string, description = string, ...}}` with string values, like OC.
+unsafe extern "C" fn computer_get_device_info(lua: *mut lua_State) -> i32 {
+    unsafe {
+        let state = get_state(lua);
+        lua_createtable(lua, 0, 0);
+        let list = lua_gettop(lua);
+
+        for (address, info) in device_info((*state).computer).iter() {
+            lua_createtable(lua, 0, 7);
+            lua_pushstring(lua, info.class.as_ptr());
+            lua_setfield(lua, -2, c"class".as_ptr());
+            lua_pushstring(lua, info.description.as_ptr());
+            lua_setfield(lua, -2, c"description".as_ptr());
+            lua_pushstring(lua, info.vendor.as_ptr());
+            lua_setfield(lua, -2, c"vendor".as_ptr());
+            lua_pushstring(lua, info.product.as_ptr());
+            lua_setfield(lua, -2, c"product".as_ptr());
+            let numbers = [
+                (c"capacity", info.capacity),
+                (c"width", info.width),
+                (c"clock", info.clock),
+            ];
+            for (key, value) in numbers {
+                if let Some(value) = value {
+                    let value = value.to_string();
+                    lua_pushlstring(lua, value.as_ptr().cast(), value.len());
+                    lua_setfield(lua, -2, key.as_ptr());
+                }
+            }
+            lua_setfield(lua, list, address.as_ptr());
+        }
+        1
+    }
+}
+
 unsafe extern "C" fn computer_set_state(lua: *mut lua_State) -> i32 {
     unsafe {
         let state = get_state(lua);
@@ -416,7 +747,14 @@ unsafe extern "C" fn computer_push_signal(lua: *mut lua_State) -> i32 {
         }
         let mut args: Vec<nn_value> = Vec::with_capacity(argc as usize);
         for i in 0..argc {
-            args.push(get_value(lua, i + 1));
+            match get_value(lua, i + 1) {
+                Ok(value) => args.push(value),
+                Err(err) => {
+                    nn_values_dropAll(args.as_mut_ptr(), args.len());
+                    luaL_argerror(lua, i + 1, err.as_ptr());
+                    return 0;
+                }
+            }
         }
         let err = nn_pushSignal((*state).computer, args.as_mut_ptr(), argc as usize);
         if !err.is_null() {
@@ -461,9 +799,37 @@ unsafe extern "C" fn component_doc(lua: *mut lua_State) -> i32 {
     }
 }
 
+/// Returns `{name = {getter = bool, setter = bool, doc = string}}` for every field, like OC.
 unsafe extern "C" fn component_fields(lua: *mut lua_State) -> i32 {
     unsafe {
+        let state = get_state(lua);
+        let addr = luaL_checklstring(lua, 1, null_mut());
+        let component = nn_findComponent((*state).computer, addr as *mut _);
+        if component.is_null() {
+            lua_pushnil(lua);
+            lua_pushstring(lua, c"no such component".as_ptr());
+            return 2;
+        }
+        let table = nn_getComponentTable(component);
         lua_createtable(lua, 0, 0);
+        let list = lua_gettop(lua);
+
+        for field in fields(table) {
+            if !nn_isMethodEnabled(component, field.name.as_ptr()) {
+                continue;
+            }
+            lua_createtable(lua, 0, 3);
+            lua_pushboolean(lua, field.getter as i32);
+            lua_setfield(lua, -2, c"getter".as_ptr());
+            lua_pushboolean(lua, field.setter as i32);
+            lua_setfield(lua, -2, c"setter".as_ptr());
+            let doc = nn_methodDoc(table, field.name.as_ptr());
+            if !doc.is_null() {
+                lua_pushstring(lua, doc);
+                lua_setfield(lua, -2, c"doc".as_ptr());
+            }
+            lua_setfield(lua, list, field.name.as_ptr());
+        }
         1
     }
 }
@@ -522,7 +888,7 @@ unsafe extern "C" fn component_methods(lua: *mut lua_State) -> i32 {
                 break;
             }
             i += 1;
-            if !nn_isMethodEnabled(component, name) {
+            if !nn_isMethodEnabled(component, name) || is_field(table, name) {
                 continue;
             }
             lua_pushboolean(lua, direct as i32);
@@ -532,6 +898,36 @@ unsafe extern "C" fn component_methods(lua: *mut lua_State) -> i32 {
     }
 }
 
+/// math.random on the machine's own generator, seeded from the host RNG so it follows the seed
+/// in deterministic mode.
+unsafe extern "C" fn math_random(lua: *mut lua_State) -> i32 {
+    let state = unsafe { get_state(lua) };
+    let r = unsafe { (*state).random.next() } as f64 / (1u64 << 31) as f64;
+    let (low, high) = match unsafe { lua_gettop(lua) } {
+        0 => {
+            unsafe { lua_pushnumber(lua, r) };
+            return 1;
+        }
+        1 => (1, unsafe { luaL_checkinteger(lua, 1) }),
+        2 => unsafe { (luaL_checkinteger(lua, 1), luaL_checkinteger(lua, 2)) },
+        _ => {
+            unsafe { luaL_error(lua, c"wrong number of arguments".as_ptr()) };
+            return 0;
+        }
+    };
+    if low > high {
+        unsafe { luaL_argerror(lua, lua_gettop(lua), c"interval is empty".as_ptr()) };
+    }
+    let span = (high as f64 - low as f64) + 1.0;
+    unsafe { lua_pushinteger(lua, low + (r * span) as i64) };
+    1
+}
+unsafe extern "C" fn math_randomseed(lua: *mut lua_State) -> i32 {
+    let seed = unsafe { luaL_checknumber(lua, 1) };
+    unsafe { (*get_state(lua)).random = Random::new(seed.to_bits()) };
+    0
+}
+
 unsafe extern "C" fn unicode_len(lua: *mut lua_State) -> i32 {
     let str = unsafe { luaL_checklstring(lua, 1, null_mut()) };
     unsafe { lua_pushinteger(lua, nn_unicode_lenPermissive(str) as i64) };
@@ -647,6 +1043,10 @@ fn load_env(lua: *mut lua_State) {
     unsafe { lua_setfield(lua, computer, c"setArchitecture".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_is_overworked), 0) };
     unsafe { lua_setfield(lua, computer, c"isOverworked".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(computer_is_timed_out), 0) };
+    unsafe { lua_setfield(lua, computer, c"isTimedOut".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(computer_is_preempted), 0) };
+    unsafe { lua_setfield(lua, computer, c"isPreempted".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_is_overheating), 0) };
     unsafe { lua_setfield(lua, computer, c"isOverheating".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_get_temperature), 0) };
@@ -659,6 +1059,8 @@ fn load_env(lua: *mut lua_State) {
     unsafe { lua_setfield(lua, computer, c"popSignal".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_users), 0) };
     unsafe { lua_setfield(lua, computer, c"users".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(computer_get_device_info), 0) };
+    unsafe { lua_setfield(lua, computer, c"getDeviceInfo".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_get_state), 0) };
     unsafe { lua_setfield(lua, computer, c"getState".as_ptr()) };
     unsafe { lua_pushcclosure(lua, Some(computer_set_state), 0) };
@@ -712,6 +1114,27 @@ fn load_env(lua: *mut lua_State) {
     unsafe { lua_pushcclosure(lua, Some(unicode_char), 0) };
     unsafe { lua_setfield(lua, unicode, c"char".as_ptr()) };
     unsafe { lua_setglobal(lua, c"unicode".as_ptr()) };
+
+    unsafe { lua_getglobal(lua, c"math".as_ptr()) };
+    let math = unsafe { lua_gettop(lua) };
+    unsafe { lua_pushcclosure(lua, Some(math_random), 0) };
+    unsafe { lua_setfield(lua, math, c"random".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(math_randomseed), 0) };
+    unsafe { lua_setfield(lua, math, c"randomseed".as_ptr()) };
+    unsafe { lua_pop(lua, 1) };
+
+    unsafe { luaL_newmetatable(lua, VALUE_METATABLE.as_ptr()) };
+    let meta = unsafe { lua_gettop(lua) };
+    unsafe { lua_pushcclosure(lua, Some(value_index), 0) };
+    unsafe { lua_setfield(lua, meta, c"__index".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(value_gc), 0) };
+    unsafe { lua_setfield(lua, meta, c"__gc".as_ptr()) };
+    unsafe { lua_pushcclosure(lua, Some(value_tostring), 0) };
+    unsafe { lua_setfield(lua, meta, c"__tostring".as_ptr()) };
+    // keeps __gc out of reach of the sandbox
+    unsafe { lua_pushstring(lua, c"userdata".as_ptr()) };
+    unsafe { lua_setfield(lua, meta, c"__metatable".as_ptr()) };
+    unsafe { lua_pop(lua, 1) };
 }
 unsafe extern "C" fn setup(computer: *mut nn_computer, _userdata: *mut c_void) -> *mut c_void {
     let alloc = unsafe { nn_getAllocator(nn_getUniverse(computer)) };
@@ -721,6 +1144,15 @@ unsafe extern "C" fn setup(computer: *mut nn_computer, _userdata: *mut c_void) -
     }
     unsafe { (*state).computer = computer };
     unsafe { (*state).mem_usage = 0 };
+    unsafe { (*state).call_budget = 0.0 };
+    unsafe { (*state).must_yield = false };
+    unsafe { (*state).tick_instructions = 0 };
+    unsafe { (*state).run_instructions = 0 };
+    unsafe { (*state).timed_out = false };
+    unsafe { (*state).preempted = false };
+    unsafe { (*state).next_heat = nn_getUptime(computer) + next_heat_interval() };
+    let seed = (next_random() as u64) << 31 | next_random() as u64;
+    unsafe { (*state).random = Random::new(seed) };
     let lua = unsafe { lua_newstate(Some(lua_alloc), state.cast()) };
     unsafe { luaL_openlibs(lua) };
     unsafe { lua_pushlightuserdata(lua, state.cast()) };
@@ -761,8 +1193,52 @@ unsafe extern "C" fn get_memory_usage(
     let state: *mut State = state.cast();
     unsafe { (*state).mem_usage }
 }
+fn next_heat_interval() -> f64 {
+    next_random() as f64 / (1u64 << 31) as f64 * 2.0 + 0.1
+}
+/// Counts instructions against the machine's limits, across all of its coroutines. Running out of
+/// the tick's budget yields whichever one runs, and so the machine, back to the host. Running past
+/// the timeout without yielding is an error, raised again on every instruction after, so pcall
+/// can't keep the machine alive, like in OC.
+unsafe extern "C" fn watchdog(lua: *mut lua_State, _ar: *mut lua_Debug) {
+    let state = unsafe { get_state(lua) };
+    let computer = unsafe { (*state).computer };
+
+    let uptime = unsafe { nn_getUptime(computer) };
+    if uptime >= unsafe { (*state).next_heat } {
+        unsafe { (*state).next_heat = uptime + next_heat_interval() };
+        unsafe { nn_addHeat(computer, next_random() as f64 / (1u64 << 31) as f64 * 3.0) };
+    }
+
+    let (_, timeout) = unsafe { crate::instruction_limits(computer) };
+    unsafe { (*state).run_instructions += HOOK_STEP as u64 };
+    if unsafe { (*state).timed_out || (*state).run_instructions >= timeout } {
+        unsafe { (*state).timed_out = true };
+        unsafe { lua_sethook(lua, Some(watchdog), LUA_MASKCOUNT, 1) };
+        unsafe { luaL_error(lua, c"too long without yielding".as_ptr()) };
+        return;
+    }
+
+    unsafe { (*state).tick_instructions -= HOOK_STEP as i64 };
+    // whichever coroutine runs is preempted, and the sandbox's resume carries the yield up to
+    // the host so user code never sees it. metamethods and the like cannot yield, so those wait
+    // for the next hook outside of them
+    let out_of_budget = unsafe { (*state).tick_instructions } <= 0;
+    if out_of_budget && unsafe { lua_isyieldable(lua) } != 0 {
+        unsafe { (*state).preempted = true };
+        unsafe { lua_yieldk(lua, 0, 0, None) };
+    }
+}
 unsafe extern "C" fn tick(computer: *mut nn_computer, state: *mut c_void, _userdata: *mut c_void) {
     let state: *mut State = state.cast();
+    unsafe { (*state).call_budget = crate::call_budget(computer) };
+    unsafe { (*state).must_yield = false };
+
+    let (per_tick, _) = unsafe { crate::instruction_limits(computer) };
+    unsafe { (*state).tick_instructions = per_tick as i64 };
+    unsafe { (*state).preempted = false };
+    // coroutines copy the hook of the thread that creates them, and all of them descend from this one
+    unsafe { lua_sethook((*state).lua, Some(watchdog), LUA_MASKCOUNT, HOOK_STEP) };
 
     let res = unsafe { lua_resume((*state).lua, null_mut(), 0) };
     match res {
@@ -770,7 +1246,12 @@ unsafe extern "C" fn tick(computer: *mut nn_computer, state: *mut c_void, _userd
             // machine halted
             unsafe { nn_setCError(computer, c"machine halted".as_ptr()) };
         }
-        LUA_YIELD => {}
+        LUA_YIELD => {
+            // a preempted machine is still in the middle of the same run
+            if !unsafe { (*state).preempted } {
+                unsafe { (*state).run_instructions = 0 };
+            }
+        }
         _ => {
             let s = unsafe { lua_tolstring((*state).lua, -1, null_mut()) };
             unsafe { nn_setError(computer, s) };
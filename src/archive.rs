@@ -0,0 +1,642 @@
+use std::{collections::BTreeMap, ffi::CStr};
+
+use crate::compression::MAX_DECOMPRESSED;
+use crate::context::get_time;
+use crate::image::{Node, normalize};
+
+const TAR_BLOCK: usize = 512;
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset.checked_add(2)?)?.try_into().ok()?))
+}
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+}
+
+/// Adds offsets and lengths read from a zip, which can point anywhere, even past the address space.
+fn zip_offset(base: usize, lengths: &[usize]) -> Result<usize, &'static CStr> {
+    lengths
+        .iter()
+        .try_fold(base, |sum, &len| sum.checked_add(len))
+        .ok_or(c"malformed zip")
+}
+
+/// Places `node` at `path`, creating any missing parent directories with the node's time.
+/// Directories never replace an existing directory, so entries listed before their parent keep their contents.
+/// A later file replaces an earlier one, as when tar appends an update, but never a directory or the other way round.
+fn insert(root: &mut BTreeMap<String, Node>, path: &str, node: Node) -> Result<(), &'static CStr> {
+    let path = normalize(path);
+    let mut parents: Vec<&str> = path.split('/').collect();
+    let Some(name) = parents.pop() else {
+        return Ok(());
+    };
+    if name.is_empty() {
+        return Ok(());
+    }
+    let modified = node.modified();
+    let mut dir = root;
+    for parent in parents {
+        let entry = dir
+            .entry(parent.to_owned())
+            .or_insert_with(|| Node::Directory {
+                children: BTreeMap::new(),
+                modified,
+            });
+        let Node::Directory { children, .. } = entry else {
+            return Err(c"file used as a directory");
+        };
+        dir = children;
+    }
+    match (dir.get_mut(name), &node) {
+        (Some(Node::Directory { modified: existing, .. }), Node::Directory { .. }) => {
+            *existing = modified;
+        }
+        (Some(Node::Directory { .. }), Node::File { .. }) => {
+            return Err(c"file conflicts with a directory");
+        }
+        (Some(Node::File { .. }), Node::Directory { .. }) => {
+            return Err(c"directory conflicts with a file");
+        }
+        _ => {
+            dir.insert(name.to_owned(), node);
+        }
+    }
+    Ok(())
+}
+
+/// Decompressed bytes an archive may still produce. It is shared by all entries, so many small
+/// ones can't add up to more than MAX_DECOMPRESSED.
+struct Budget(usize);
+
+impl Budget {
+    fn take(&mut self, len: usize) -> Result<(), &'static CStr> {
+        self.0 = self.0.checked_sub(len).ok_or(c"archive is too large")?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    get_time() as u64
+}
+
+/// Converts an MS-DOS date and time, as stored in zip headers, to seconds since the unix epoch.
+/// Zip has no time zone, so it is taken as UTC.
+fn dos_time(date: u16, time: u16) -> u64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).clamp(1, 12) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    // days since the epoch, from Howard Hinnant's days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let seconds = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3f) as i64 * 60 + (time & 0x1f) as i64 * 2;
+    (days * 86400 + seconds) as u64
+}
+
+pub fn is_tar(bytes: &[u8]) -> bool {
+    bytes.len() >= TAR_BLOCK && bytes.get(257..262) == Some(&b"ustar"[..])
+}
+
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06")
+}
+
+fn tar_str(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+fn tar_number(field: &[u8]) -> Result<u64, &'static CStr> {
+    // GNU base-256 encoding for values that don't fit in octal
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut n = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            n = n.checked_mul(256).ok_or(c"tar number out of range")? | b as u64;
+        }
+        return Ok(n);
+    }
+    let digits = tar_str(field);
+    let digits = std::str::from_utf8(digits).map_err(|_| c"invalid tar number")?;
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| c"invalid tar number")
+}
+
+/// Parses the `"<len> <key>=<value>\n"` records of a pax extended header.
+fn pax_records(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut records = Vec::new();
+    while let Some(space) = data.iter().position(|&b| b == b' ') {
+        let Some(len) = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+        else {
+            break;
+        };
+        let Some(record) = data.get(space + 1..len) else {
+            break;
+        };
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            records.push((&record[..eq], &record[eq + 1..]));
+        }
+        data = &data[len..];
+    }
+    records
+}
+
+/// Reads a ustar, pax or GNU tar archive. Links and device nodes are skipped.
+pub fn read_tar(bytes: &[u8]) -> Result<Node, &'static CStr> {
+    read_tar_within(bytes, Budget(MAX_DECOMPRESSED))
+}
+
+fn read_tar_within(bytes: &[u8], mut budget: Budget) -> Result<Node, &'static CStr> {
+    let mut root = BTreeMap::new();
+    let mut pos = 0;
+    let mut long_name: Option<Vec<u8>> = None;
+    let mut pax_path: Option<Vec<u8>> = None;
+    let mut pax_size: Option<u64> = None;
+    let mut pax_mtime: Option<u64> = None;
+
+    while let Some(header) = bytes.get(pos..pos + TAR_BLOCK) {
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let checksum = tar_number(&header[148..156])?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+            .sum();
+        if sum != checksum {
+            return Err(c"bad tar header checksum");
+        }
+
+        let size = match pax_size.take() {
+            Some(size) => size,
+            None => tar_number(&header[124..136])?,
+        };
+        let size = usize::try_from(size).map_err(|_| c"truncated tar entry")?;
+        let start = pos + TAR_BLOCK;
+        let data = start
+            .checked_add(size)
+            .and_then(|end| bytes.get(start..end))
+            .ok_or(c"truncated tar entry")?;
+        pos = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        let kind = header[156];
+        match kind {
+            b'x' => {
+                for (key, value) in pax_records(data) {
+                    match key {
+                        b"path" => pax_path = Some(value.to_vec()),
+                        b"size" => {
+                            let size = std::str::from_utf8(value).ok().and_then(|v| v.parse::<u64>().ok());
+                            pax_size = Some(size.ok_or(c"invalid pax size")?);
+                        }
+                        // may have a fractional part, which OC has no use for
+                        b"mtime" => {
+                            let seconds = value.split(|&b| b == b'.').next().unwrap_or_default();
+                            pax_mtime = std::str::from_utf8(seconds).ok().and_then(|v| v.parse::<u64>().ok());
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            b'L' => {
+                long_name = Some(tar_str(data).to_vec());
+                continue;
+            }
+            b'g' => continue,
+            _ => {}
+        }
+
+        let name = match (pax_path.take(), long_name.take()) {
+            (Some(path), _) | (None, Some(path)) => path,
+            (None, None) => {
+                let mut name = Vec::new();
+                let prefix = tar_str(&header[345..500]);
+                if header[257..262] == *b"ustar" && header[262] == 0 && !prefix.is_empty() {
+                    name.extend_from_slice(prefix);
+                    name.push(b'/');
+                }
+                name.extend_from_slice(tar_str(&header[0..100]));
+                name
+            }
+        };
+        let name = String::from_utf8_lossy(&name);
+        let modified = match pax_mtime.take() {
+            Some(mtime) => mtime,
+            None => tar_number(&header[136..148])?,
+        };
+
+        match kind {
+            b'0' | b'\0' | b'7' => {
+                budget.take(data.len())?;
+                insert(
+                    &mut root,
+                    &name,
+                    Node::File {
+                        data: data.to_vec(),
+                        modified,
+                    },
+                )?
+            }
+            b'5' => insert(
+                &mut root,
+                &name,
+                Node::Directory {
+                    children: BTreeMap::new(),
+                    modified,
+                },
+            )?,
+            _ => {}
+        }
+    }
+    Ok(Node::Directory {
+        children: root,
+        modified: now(),
+    })
+}
+
+/// Reads a zip archive through its central directory. Only stored and deflated entries are supported.
+pub fn read_zip(bytes: &[u8]) -> Result<Node, &'static CStr> {
+    read_zip_within(bytes, Budget(MAX_DECOMPRESSED))
+}
+
+fn read_zip_within(bytes: &[u8], mut budget: Budget) -> Result<Node, &'static CStr> {
+    const END_OF_DIRECTORY: u32 = 0x06054b50;
+    const DIRECTORY_ENTRY: u32 = 0x02014b50;
+    const LOCAL_HEADER: u32 = 0x04034b50;
+
+    let end = (0..bytes.len().saturating_sub(21))
+        .rev()
+        .take(65536 + 22)
+        .find(|&i| read_u32(bytes, i) == Some(END_OF_DIRECTORY))
+        .ok_or(c"missing zip end of central directory")?;
+    let count = read_u16(bytes, end + 10).ok_or(c"truncated zip")? as usize;
+    let mut pos = read_u32(bytes, end + 16).ok_or(c"truncated zip")? as usize;
+
+    let mut root = BTreeMap::new();
+    for _ in 0..count {
+        if read_u32(bytes, pos) != Some(DIRECTORY_ENTRY) {
+            return Err(c"corrupt zip central directory");
+        }
+        let method = read_u16(bytes, pos + 10).ok_or(c"truncated zip")?;
+        let time = read_u16(bytes, pos + 12).ok_or(c"truncated zip")?;
+        let date = read_u16(bytes, pos + 14).ok_or(c"truncated zip")?;
+        let modified = dos_time(date, time);
+        let compressed = read_u32(bytes, pos + 20).ok_or(c"truncated zip")? as usize;
+        let uncompressed = read_u32(bytes, pos + 24).ok_or(c"truncated zip")? as usize;
+        let name_len = read_u16(bytes, pos + 28).ok_or(c"truncated zip")? as usize;
+        let extra_len = read_u16(bytes, pos + 30).ok_or(c"truncated zip")? as usize;
+        let comment_len = read_u16(bytes, pos + 32).ok_or(c"truncated zip")? as usize;
+        let local = read_u32(bytes, pos + 42).ok_or(c"truncated zip")? as usize;
+        let name_start = zip_offset(pos, &[46])?;
+        let name = bytes
+            .get(name_start..zip_offset(name_start, &[name_len])?)
+            .ok_or(c"truncated zip")?;
+        let name = String::from_utf8_lossy(name);
+        pos = zip_offset(name_start, &[name_len, extra_len, comment_len])?;
+
+        if name.ends_with('/') {
+            insert(
+                &mut root,
+                &name,
+                Node::Directory {
+                    children: BTreeMap::new(),
+                    modified,
+                },
+            )?;
+            continue;
+        }
+
+        if read_u32(bytes, local) != Some(LOCAL_HEADER) {
+            return Err(c"corrupt zip local header");
+        }
+        let local_name_len = read_u16(bytes, local + 26).ok_or(c"truncated zip")? as usize;
+        let local_extra_len = read_u16(bytes, local + 28).ok_or(c"truncated zip")? as usize;
+        let start = zip_offset(local, &[30, local_name_len, local_extra_len])?;
+        let raw = bytes
+            .get(start..zip_offset(start, &[compressed])?)
+            .ok_or(c"truncated zip entry")?;
+        // the sizes are only what the archive claims, so they must not decide how much is allocated
+        let limit = uncompressed.min(budget.0);
+        let data = match method {
+            0 => raw.to_vec(),
+            8 => miniz_oxide::inflate::decompress_to_vec_with_limit(raw, limit)
+                .map_err(|_| c"corrupt or oversized zip entry")?,
+            _ => return Err(c"unsupported zip compression method"),
+        };
+        budget.take(data.len())?;
+        insert(&mut root, &name, Node::File { data, modified })?;
+    }
+    Ok(Node::Directory {
+        children: root,
+        modified: now(),
+    })
+}
+
+/// One entry of an archive being written. Directories have no data.
+pub struct TarEntry<'a> {
+    pub path: String,
+    pub data: Option<&'a [u8]>,
+    pub modified: u64,
+}
+
+fn tar_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{value:0width$o}");
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+}
+
+fn tar_header(name: &[u8], kind: u8, size: usize, modified: u64) -> [u8; TAR_BLOCK] {
+    let mut header = [0; TAR_BLOCK];
+    let name_len = name.len().min(100);
+    header[..name_len].copy_from_slice(&name[..name_len]);
+    tar_octal(&mut header[100..108], if kind == b'5' { 0o755 } else { 0o644 });
+    tar_octal(&mut header[108..116], 0);
+    tar_octal(&mut header[116..124], 0);
+    tar_octal(&mut header[124..136], size as u64);
+    tar_octal(&mut header[136..148], modified);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].fill(b' ');
+    let sum: u64 = header.iter().map(|&b| b as u64).sum();
+    tar_octal(&mut header[148..155], sum);
+    header
+}
+
+fn push_padded(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(data);
+    out.resize(out.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+}
+
+/// A pax record's length prefix counts its own digits.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {key}={value}\n");
+    let mut len = body.len() + 1;
+    while len != body.len() + len.to_string().len() {
+        len = body.len() + len.to_string().len();
+    }
+    format!("{len}{body}")
+}
+
+/// Writes a ustar archive that read_tar can load again, using pax headers for long paths.
+pub fn write_tar(entries: &[TarEntry]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for entry in entries {
+        let mut path = entry.path.clone();
+        if entry.data.is_none() {
+            path.push('/');
+        }
+        if path.len() > 100 {
+            let record = pax_record("path", &path);
+            out.extend_from_slice(&tar_header(b"././@PaxHeader", b'x', record.len(), entry.modified));
+            push_padded(&mut out, record.as_bytes());
+        }
+        match entry.data {
+            Some(data) => {
+                out.extend_from_slice(&tar_header(path.as_bytes(), b'0', data.len(), entry.modified));
+                push_padded(&mut out, data);
+            }
+            None => out.extend_from_slice(&tar_header(path.as_bytes(), b'5', 0, entry.modified)),
+        }
+    }
+    out.resize(out.len() + 2 * TAR_BLOCK, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file<'a>(root: &'a Node, path: &str) -> &'a [u8] {
+        let node = path.split('/').fold(root, |node, name| node.child(name).unwrap());
+        match node {
+            Node::File { data, .. } => data,
+            Node::Directory { .. } => panic!("{path} is a directory"),
+        }
+    }
+
+    /// A stored zip with one entry per (name, data), all dated 2024-02-29 12:34:56.
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let (date, time) = ((44 << 9) | (2 << 5) | 29, (12 << 11) | (34 << 5) | 28);
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in entries {
+            let local = out.len() as u32;
+            out.extend_from_slice(b"PK\x03\x04\x14\0\0\0\0\0");
+            for field in [time, date] {
+                out.extend_from_slice(&u16::to_le_bytes(field));
+            }
+            out.extend_from_slice(&[0; 4]);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&[0; 2]);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\0\x14\0\0\0\0\0");
+            for field in [time, date] {
+                directory.extend_from_slice(&u16::to_le_bytes(field));
+            }
+            directory.extend_from_slice(&[0; 4]);
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&local.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let start = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(b"PK\x05\x06\0\0\0\0");
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&[0; 2]);
+        out
+    }
+
+    #[test]
+    fn dos_time_is_utc() {
+        assert_eq!(dos_time((1 << 5) | 1, 0), 315532800);
+        let (date, time) = ((44 << 9) | (2 << 5) | 29, (12 << 11) | (34 << 5) | 28);
+        assert_eq!(dos_time(date, time), 1709210096);
+        // month and day 0 are invalid, but must not underflow
+        assert_eq!(dos_time(0, 0), 315532800);
+        dos_time(u16::MAX, u16::MAX);
+    }
+
+    #[test]
+    fn pax_record_counts_its_own_length() {
+        assert_eq!(pax_record("path", "a"), "9 path=a\n");
+        for len in 80..120 {
+            let record = pax_record("path", &"a".repeat(len));
+            let (prefix, _) = record.split_once(' ').unwrap();
+            assert_eq!(prefix.parse::<usize>().unwrap(), record.len());
+            assert_eq!(pax_records(record.as_bytes()), [(&b"path"[..], "a".repeat(len).as_bytes())]);
+        }
+    }
+
+    #[test]
+    fn pax_records_stop_at_garbage() {
+        let data = b"12 mtime=10\nnope\n99 path=x\n";
+        assert_eq!(pax_records(data), [(&b"mtime"[..], &b"10"[..])]);
+        assert!(pax_records(b"0 \n").is_empty());
+        assert!(pax_records(b"1 ").is_empty());
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let long = format!("dir/{}", "x".repeat(150));
+        let tar = write_tar(&[
+            TarEntry {
+                path: "dir".to_owned(),
+                data: None,
+                modified: 5,
+            },
+            TarEntry {
+                path: long.clone(),
+                data: Some(b"long"),
+                modified: 7,
+            },
+            TarEntry {
+                path: "dir/file".to_owned(),
+                data: Some(&[1; 600]),
+                modified: 1_700_000_000,
+            },
+        ]);
+        assert!(is_tar(&tar));
+        let root = read_tar(&tar).unwrap();
+        assert_eq!(file(&root, &long), b"long");
+        assert_eq!(file(&root, "dir/file"), [1; 600]);
+        assert_eq!(root.child("dir").unwrap().child("file").unwrap().modified(), 1_700_000_000);
+        assert_eq!(root.child("dir").unwrap().modified(), 5);
+    }
+
+    #[test]
+    fn tar_rejects_malformed_input() {
+        let tar = write_tar(&[TarEntry {
+            path: "file".to_owned(),
+            data: Some(&[1; 600]),
+            modified: 0,
+        }]);
+        let mut bad = tar.clone();
+        bad[0] = b'g';
+        assert!(read_tar(&bad).is_err());
+        assert!(read_tar(&tar[..TAR_BLOCK + 100]).is_err());
+
+        // a size that runs past the end of the address space
+        let mut huge = tar_header(b"file", b'0', 0, 0);
+        huge[124] = 0xff;
+        huge[125..136].fill(0xff);
+        huge[148..156].fill(b' ');
+        let sum: u64 = huge.iter().map(|&b| b as u64).sum();
+        tar_octal(&mut huge[148..155], sum);
+        assert!(read_tar(&huge).is_err());
+    }
+
+    #[test]
+    fn tar_file_cannot_replace_directory() {
+        let tar = write_tar(&[
+            TarEntry {
+                path: "dir/file".to_owned(),
+                data: Some(b"data"),
+                modified: 0,
+            },
+            TarEntry {
+                path: "dir".to_owned(),
+                data: Some(b"oops"),
+                modified: 0,
+            },
+        ]);
+        assert!(read_tar(&tar).is_err());
+    }
+
+    #[test]
+    fn tar_directory_after_its_contents_keeps_them() {
+        let tar = write_tar(&[
+            TarEntry {
+                path: "dir/file".to_owned(),
+                data: Some(b"data"),
+                modified: 0,
+            },
+            TarEntry {
+                path: "dir".to_owned(),
+                data: None,
+                modified: 9,
+            },
+        ]);
+        let root = read_tar(&tar).unwrap();
+        assert_eq!(file(&root, "dir/file"), b"data");
+        assert_eq!(root.child("dir").unwrap().modified(), 9);
+    }
+
+    #[test]
+    fn tar_base_256_numbers() {
+        let mut field = [0u8; 12];
+        field[0] = 0x80;
+        field[10..].copy_from_slice(&[1, 2]);
+        assert_eq!(tar_number(&field), Ok(0x102));
+        assert!(tar_number(&[0xff; 12]).is_err());
+        assert_eq!(tar_number(b"0000644 \0"), Ok(0o644));
+        assert!(tar_number(b"12x\0").is_err());
+    }
+
+    #[test]
+    fn zip_reads_stored_entries() {
+        let bytes = zip(&[("dir/", b""), ("dir/file", b"hello"), ("top", b"")]);
+        assert!(is_zip(&bytes));
+        let root = read_zip(&bytes).unwrap();
+        assert_eq!(file(&root, "dir/file"), b"hello");
+        assert_eq!(file(&root, "top"), b"");
+        assert_eq!(root.child("dir").unwrap().child("file").unwrap().modified(), 1709210096);
+    }
+
+    #[test]
+    fn zip_rejects_malformed_offsets() {
+        let bytes = zip(&[("file", b"hello")]);
+        let directory = bytes.len() - 22 - 46 - 4;
+        let patch = |offset: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            read_zip(&bytes)
+        };
+        // local header offset, compressed size, name length and central directory offset
+        assert!(patch(directory + 42, &[0xff; 4]).is_err());
+        assert!(patch(directory + 20, &[0xff; 4]).is_err());
+        assert!(patch(directory + 28, &[0xff; 2]).is_err());
+        assert!(patch(bytes.len() - 6, &[0xff; 4]).is_err());
+        assert!(read_zip(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_zip(b"PK\x05\x06").is_err());
+    }
+
+    #[test]
+    fn zip_entries_share_one_budget() {
+        let bytes = zip(&[("a", b"hello"), ("b", b"world")]);
+        assert!(read_zip_within(&bytes, Budget(9)).is_err());
+        assert!(read_zip_within(&bytes, Budget(10)).is_ok());
+    }
+
+    #[test]
+    fn tar_entries_share_one_budget() {
+        let entries = ["a", "b"].map(|path| TarEntry {
+            path: path.to_owned(),
+            data: Some(b"hello"),
+            modified: 0,
+        });
+        let tar = write_tar(&entries);
+        assert!(read_tar_within(&tar, Budget(9)).is_err());
+        assert!(read_tar_within(&tar, Budget(10)).is_ok());
+    }
+}
@@ -0,0 +1,155 @@
+--[[
+Copyright (c) 2013-2015 Florian "Sangar" Nücke
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+]]
+
+-- Built-in BIOS, installed when the host supplies no EEPROM.
+-- Boots /init.lua like the OC Lua BIOS, and lets the user pick a device when several are bootable.
+-- boot_invoke and tryLoadFrom come from OpenComputers' Lua BIOS, under the license above.
+
+local init
+do
+  local component_invoke = component.invoke
+  local function boot_invoke(address, method, ...)
+    local result = table.pack(pcall(component_invoke, address, method, ...))
+    if not result[1] then
+      return nil, result[2]
+    else
+      return table.unpack(result, 2, result.n)
+    end
+  end
+
+  local eeprom = component.list("eeprom")()
+  computer.getBootAddress = function()
+    return boot_invoke(eeprom, "getData")
+  end
+  computer.setBootAddress = function(address)
+    return boot_invoke(eeprom, "setData", address)
+  end
+
+  local gpu = component.list("gpu")()
+  do
+    local screen = component.list("screen")()
+    if gpu and screen then
+      boot_invoke(gpu, "bind", screen)
+    else
+      gpu = nil
+    end
+  end
+
+  local function tryLoadFrom(address)
+    local handle, reason = boot_invoke(address, "open", "/init.lua")
+    if not handle then
+      return nil, reason
+    end
+    local buffer = ""
+    repeat
+      local data, reason = boot_invoke(address, "read", handle, math.maxinteger or math.huge)
+      if not data and reason then
+        return nil, reason
+      end
+      buffer = buffer .. (data or "")
+    until not data
+    boot_invoke(address, "close", handle)
+    return load(buffer, "=init")
+  end
+
+  local function isBootable(address)
+    return boot_invoke(address, "exists", "/init.lua") and not boot_invoke(address, "isDirectory", "/init.lua")
+  end
+
+  local bootable = {}
+  for address in component.list("filesystem") do
+    if isBootable(address) then
+      table.insert(bootable, address)
+    end
+  end
+  table.sort(bootable)
+
+  local function describe(address)
+    local label = boot_invoke(address, "getLabel")
+    if label and #label > 0 then
+      return label .. " (" .. address:sub(1, 8) .. ")"
+    end
+    return address
+  end
+
+  -- shows every bootable device for a few seconds, returns the chosen one or nil to keep the default
+  local function pick(default)
+    if not gpu or #bootable < 2 then
+      return nil
+    end
+    local w, h = boot_invoke(gpu, "getResolution")
+    boot_invoke(gpu, "setBackground", 0x000000)
+    boot_invoke(gpu, "setForeground", 0xFFFFFF)
+    boot_invoke(gpu, "fill", 1, 1, w, h, " ")
+    boot_invoke(gpu, "set", 1, 1, "Select a boot device, or wait to boot the default:")
+    for i, address in ipairs(bootable) do
+      local marker = address == default and "*" or " "
+      boot_invoke(gpu, "set", 1, i + 2, marker .. " " .. i .. ") " .. describe(address))
+    end
+    local choice
+    local deadline = computer.uptime() + 3
+    while computer.uptime() < deadline do
+      local signal, _, char, code = computer.pullSignal(deadline - computer.uptime())
+      if signal == "key_down" then
+        if code == 0x1C then -- enter
+          break
+        end
+        local n = char and char >= 0x31 and char <= 0x39 and char - 0x30
+        if n and bootable[n] then
+          choice = bootable[n]
+          break
+        end
+      end
+    end
+    boot_invoke(gpu, "fill", 1, 1, w, h, " ")
+    return choice
+  end
+
+  local default = computer.getBootAddress()
+  if not default or default == "" or not isBootable(default) then
+    default = bootable[1]
+  end
+  local address = pick(default) or default
+
+  local reason
+  if address then
+    init, reason = tryLoadFrom(address)
+    if init then
+      computer.setBootAddress(address)
+    end
+  end
+  if not init then
+    computer.setBootAddress("")
+    for _, address in ipairs(bootable) do
+      init, reason = tryLoadFrom(address)
+      if init then
+        computer.setBootAddress(address)
+        break
+      end
+    end
+  end
+  if not init then
+    error("no bootable medium found" .. (reason and (": " .. tostring(reason)) or ""), 0)
+  end
+  computer.beep(1000, 0.2)
+end
+return init()
@@ -0,0 +1,73 @@
+use std::{borrow::Cow, ffi::CStr, io::Read};
+
+use ruzstd::decoding::StreamingDecoder;
+
+/// Caps decompressed output so a tiny upload can't exhaust wasm memory.
+pub const MAX_DECOMPRESSED: usize = 64 * 1024 * 1024;
+
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+fn is_zstd(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd])
+}
+
+/// Decompresses gzip or zstd input, detected by magic bytes. Anything else is passed through.
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, &'static CStr> {
+    if is_gzip(bytes) {
+        gunzip(bytes).map(Cow::Owned)
+    } else if is_zstd(bytes) {
+        unzstd(bytes).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+/// Strips the gzip framing and inflates the payload.
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, &'static CStr> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if bytes.len() < 18 || bytes[2] != 8 {
+        return Err(c"unsupported gzip stream");
+    }
+    let flags = bytes[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = bytes
+            .get(pos..pos + 2)
+            .ok_or(c"truncated gzip header")?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let len = bytes
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or(c"truncated gzip header")?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let payload = bytes.get(pos..).ok_or(c"truncated gzip header")?;
+    miniz_oxide::inflate::decompress_to_vec_with_limit(payload, MAX_DECOMPRESSED)
+        .map_err(|_| c"corrupt or oversized gzip stream")
+}
+
+fn unzstd(bytes: &[u8]) -> Result<Vec<u8>, &'static CStr> {
+    let decoder = StreamingDecoder::new(bytes).map_err(|_| c"corrupt zstd stream")?;
+    let mut out = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| c"corrupt zstd stream")?;
+    if out.len() > MAX_DECOMPRESSED {
+        return Err(c"zstd stream is too large");
+    }
+    Ok(out)
+}
@@ -1,4 +1,8 @@
-use std::{alloc::Layout, ffi::c_void, ptr::null_mut};
+use std::{
+    alloc::Layout,
+    ffi::{CString, c_void},
+    ptr::null_mut,
+};
 
 use neonucleus::ffi::{nn_Alloc, nn_Clock, nn_Context, nn_Rng, nn_noMutex};
 
@@ -8,8 +12,59 @@ unsafe extern "C" {
     fn _get_time() -> f64;
 }
 
+/// Replaces the wall clock in deterministic mode.
+#[derive(Clone, Copy)]
+struct VirtualClock {
+    settings: Deterministic,
+    now: f64,
+}
+
+/// What enable_deterministic was called with. Replays must run with the same settings.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Deterministic {
+    pub seed: u64,
+    pub start: f64,
+    pub step: f64,
+}
+
+static mut VIRTUAL_CLOCK: Option<VirtualClock> = None;
+/// Added to the host's clock, so time stands still while a snapshot is stored.
+static mut CLOCK_OFFSET: f64 = 0.0;
+
+/// Seconds since the epoch, from the host or from the virtual clock in deterministic mode.
 pub fn get_time() -> f64 {
-    unsafe { _get_time() }
+    match unsafe { VIRTUAL_CLOCK } {
+        Some(clock) => clock.now,
+        None => unsafe { _get_time() + CLOCK_OFFSET },
+    }
+}
+
+/// Makes the wall clock read `now` and carry on from there.
+pub fn resume_wall_clock(now: f64) {
+    unsafe { CLOCK_OFFSET = now - _get_time() };
+}
+
+/// Seeds the RNG and stops the clock at `start`, after which it only moves by `step` seconds
+/// per advance_clock.
+pub fn set_deterministic(settings: Deterministic) {
+    seed_random(settings.seed);
+    unsafe {
+        VIRTUAL_CLOCK = Some(VirtualClock {
+            settings,
+            now: settings.start,
+        })
+    };
+}
+
+pub fn deterministic() -> Option<Deterministic> {
+    unsafe { VIRTUAL_CLOCK }.map(|clock| clock.settings)
+}
+
+/// Moves the virtual clock one step forward. Does nothing on the wall clock.
+pub fn advance_clock() {
+    if let Some(clock) = unsafe { &mut *&raw mut VIRTUAL_CLOCK } {
+        clock.now += clock.settings.step;
+    }
 }
 
 pub fn align_up(size: usize, align: usize) -> usize {
@@ -52,16 +107,58 @@ unsafe extern "C" fn time(_: *mut c_void) -> f64 {
     get_time()
 }
 
-static mut SEED: u64 = 0;
+/// musl's rand: https://git.musl-libc.org/cgit/musl/tree/src/prng/rand.c
+/// The host's one is shared by neonucleus and every computer.
+#[derive(Clone, Copy)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// The next 31 random bits.
+    pub fn next(&mut self) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (self.0 >> 33) as usize
+    }
+}
+
+static mut SEED: Random = Random(0);
 
-// random func: https://git.musl-libc.org/cgit/musl/tree/src/prng/rand.c
 unsafe extern "C" fn random(_: *mut c_void) -> usize {
-    unsafe { SEED = SEED.wrapping_mul(6364136223846793005).wrapping_add(1) };
-    (unsafe { SEED } >> 33) as usize
+    unsafe { &mut *&raw mut SEED }.next()
+}
+
+/// A random UUID, for devices that are not components and so get no address from neonucleus.
+pub fn random_address() -> CString {
+    let mut bytes = [0u8; 16];
+    for byte in &mut bytes {
+        *byte = next_random() as u8;
+    }
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let mut address = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            address.push('-');
+        }
+        address.push_str(&format!("{byte:02x}"));
+    }
+    CString::new(address).unwrap()
+}
+
+/// The next 31 random bits, from the same generator as neonucleus uses.
+pub fn next_random() -> usize {
+    unsafe { random(null_mut()) }
+}
+
+fn seed_random(seed: u64) {
+    unsafe { SEED = Random::new(seed) };
 }
 
 pub fn init_random() {
-    unsafe { SEED = get_time().to_bits() };
+    seed_random(get_time().to_bits());
 }
 pub fn get_context() -> nn_Context {
     nn_Context {
@@ -0,0 +1,133 @@
+use std::ffi::{CStr, CString, c_char};
+
+use neonucleus::ffi::{nn_component, nn_computer, nn_getComponentAddress};
+
+pub const DEFAULT_VENDOR: &CStr = c"MightyPirates GmbH & Co. KG";
+pub const SCUMMTECH: &CStr = c"Scummtech, Inc.";
+/// The product every OC filesystem reports, whatever medium it is on.
+pub const FILESYSTEM: &CStr = c"MPFS.21.6";
+
+/// What computer.getDeviceInfo reports about a component or built-in part, like OC's DeviceInfo.
+pub struct DeviceInfo {
+    pub class: &'static CStr,
+    pub description: &'static CStr,
+    pub vendor: &'static CStr,
+    pub product: CString,
+    pub capacity: Option<usize>,
+    pub width: Option<usize>,
+    pub clock: Option<usize>,
+}
+
+impl DeviceInfo {
+    pub fn new(
+        class: &'static CStr,
+        description: &'static CStr,
+        product: impl Into<CString>,
+    ) -> Self {
+        Self {
+            class,
+            description,
+            vendor: DEFAULT_VENDOR,
+            product: product.into(),
+            capacity: None,
+            width: None,
+            clock: None,
+        }
+    }
+
+    pub const fn vendor(mut self, vendor: &'static CStr) -> Self {
+        self.vendor = vendor;
+        self
+    }
+
+    pub const fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub const fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub const fn clock(mut self, clock: usize) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+/// What is known about a computer's devices, by address. neonucleus keeps no such metadata.
+#[derive(Default)]
+pub struct Devices(Vec<(CString, DeviceInfo)>);
+
+impl Devices {
+    /// Records the device at `address`, replacing what was known about it.
+    pub fn set(&mut self, address: &CStr, info: DeviceInfo) {
+        self.remove(address);
+        self.0.push((address.to_owned(), info));
+    }
+
+    pub fn remove(&mut self, address: &CStr) {
+        self.0.retain(|(a, _)| a.as_c_str() != address);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&CStr, &DeviceInfo)> {
+        self.0.iter().map(|(address, info)| (address.as_c_str(), info))
+    }
+}
+
+/// Records the device at `address`, replacing what was known about it.
+///
+/// # Safety
+/// computer must be valid and created by new_computer, and address must be a valid C string
+pub unsafe fn set_device_info(
+    computer: *mut nn_computer,
+    address: *const c_char,
+    info: DeviceInfo,
+) {
+    let address = unsafe { CStr::from_ptr(address) };
+    unsafe { crate::devices(computer) }.set(address, info);
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer, and component must belong to it
+pub unsafe fn set_component_info(
+    computer: *mut nn_computer,
+    component: *mut nn_component,
+    info: DeviceInfo,
+) {
+    unsafe { set_device_info(computer, nn_getComponentAddress(component), info) };
+}
+
+/// Forgets the device at `address`, once its component is removed.
+///
+/// # Safety
+/// computer must be valid and created by new_computer, and address must be a valid C string
+pub unsafe fn remove_device_info(computer: *mut nn_computer, address: *const c_char) {
+    let address = unsafe { CStr::from_ptr(address) };
+    unsafe { crate::devices(computer) }.remove(address);
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer
+pub unsafe fn device_info<'a>(computer: *mut nn_computer) -> &'a Devices {
+    unsafe { crate::devices(computer) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_are_replaced_and_removed_by_address() {
+        let mut devices = Devices::default();
+        devices.set(c"a", DeviceInfo::new(c"disk", c"Hard disk drive", c"Catfish 1024L2"));
+        devices.set(c"b", DeviceInfo::new(c"memory", c"EEPROM", c"FlashStick2k"));
+        devices.set(c"a", DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(10));
+        let list: Vec<_> = devices.iter().map(|(a, info)| (a, info.class)).collect();
+        assert_eq!(list, [(c"b", c"memory"), (c"a", c"volume")]);
+
+        devices.remove(c"b");
+        assert_eq!(devices.iter().count(), 1);
+    }
+}
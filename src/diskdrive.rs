@@ -0,0 +1,173 @@
+use std::{
+    ffi::{CStr, CString, c_void},
+    ptr::null_mut,
+};
+
+use crate::devices::{DeviceInfo, FILESYSTEM, SCUMMTECH, remove_device_info, set_component_info};
+
+use neonucleus::ffi::{
+    nn_addFileSystem, nn_component, nn_componentTable, nn_computer, nn_defineMethod,
+    nn_destroyFilesystem, nn_filesystem, nn_findComponent, nn_getAllocator, nn_getComponentAddress,
+    nn_getUniverse, nn_newComponent, nn_newComponentTable, nn_removeComponent, nn_return,
+    nn_universe, nn_values_boolean, nn_values_nil, nn_values_string,
+};
+
+static mut DISK_DRIVE_TABLE: *mut nn_componentTable = null_mut();
+
+/// A drive that floppies can be inserted into and ejected from while the computer runs.
+pub struct DiskDrive {
+    computer: *mut nn_computer,
+    media: Option<CString>,
+}
+
+impl DiskDrive {
+    pub fn computer(&self) -> *mut nn_computer {
+        self.computer
+    }
+
+    /// Adds the medium as a filesystem component, ejecting whatever was inserted before.
+    /// fs is destroyed if it can't be added.
+    ///
+    /// # Safety
+    /// fs must be a filesystem not yet added to any computer
+    pub unsafe fn insert(&mut self, fs: *mut nn_filesystem) -> Result<(), &'static CStr> {
+        unsafe { self.eject() };
+        let component = unsafe { nn_addFileSystem(self.computer, null_mut(), -1, fs) };
+        if component.is_null() {
+            unsafe { nn_destroyFilesystem(fs) };
+            return Err(c"too many components");
+        }
+        let address = unsafe { CStr::from_ptr(nn_getComponentAddress(component)) }.to_owned();
+        let info = DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(512 * 1024);
+        unsafe { set_component_info(self.computer, component, info) };
+        self.media = Some(address);
+        Ok(())
+    }
+
+    /// Removes the current medium. Returns false if the drive was empty.
+    /// neonucleus announces the media coming and going with component_added and component_removed.
+    ///
+    /// # Safety
+    /// the drive's computer must still exist
+    pub unsafe fn eject(&mut self) -> bool {
+        let Some(address) = self.media.take() else {
+            return false;
+        };
+        // gone already if the computer is being torn down and got to it first
+        if !unsafe { nn_findComponent(self.computer, address.as_ptr().cast_mut()) }.is_null() {
+            unsafe { nn_removeComponent(self.computer, address.as_ptr().cast_mut()) };
+        }
+        unsafe { remove_device_info(self.computer, address.as_ptr()) };
+        true
+    }
+}
+
+unsafe fn disk_drive<'a>(userdata: *mut c_void) -> &'a mut DiskDrive {
+    unsafe { &mut *userdata.cast() }
+}
+
+/// Takes the media out with the drive, so it can't stay mounted without one.
+unsafe extern "C" fn disk_drive_destroy(
+    _table_userdata: *mut c_void,
+    _component: *mut nn_component,
+    userdata: *mut c_void,
+) {
+    let mut drive = unsafe { Box::from_raw(userdata.cast::<DiskDrive>()) };
+    unsafe { drive.eject() };
+}
+unsafe extern "C" fn disk_drive_is_empty(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { disk_drive(userdata) };
+    unsafe { nn_return(computer, nn_values_boolean(drive.media.is_none())) };
+}
+unsafe extern "C" fn disk_drive_eject(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { disk_drive(userdata) };
+    unsafe { nn_return(computer, nn_values_boolean(drive.eject())) };
+}
+unsafe extern "C" fn disk_drive_media(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { disk_drive(userdata) };
+    let value = match &drive.media {
+        Some(address) => {
+            let alloc = unsafe { nn_getAllocator(nn_getUniverse(computer)) };
+            unsafe { nn_values_string(alloc, address.as_ptr(), address.count_bytes()) }
+        }
+        None => unsafe { nn_values_nil() },
+    };
+    unsafe { nn_return(computer, value) };
+}
+
+/// Registers the `disk_drive` component type. Called once from init.
+pub fn init_disk_drive_table(universe: *mut nn_universe) {
+    let alloc = unsafe { nn_getAllocator(universe) };
+    let table = unsafe {
+        nn_newComponentTable(
+            alloc,
+            c"disk_drive".as_ptr(),
+            null_mut(),
+            None,
+            Some(disk_drive_destroy),
+        )
+    };
+    assert_ne!(table, null_mut());
+    unsafe {
+        nn_defineMethod(
+            table,
+            c"isEmpty".as_ptr(),
+            true,
+            Some(disk_drive_is_empty),
+            null_mut(),
+            c"isEmpty():boolean -- Checks whether some medium is currently in the drive.".as_ptr(),
+        );
+        nn_defineMethod(
+            table,
+            c"eject".as_ptr(),
+            true,
+            Some(disk_drive_eject),
+            null_mut(),
+            c"eject([velocity:number]):boolean -- Eject the currently present medium from the drive.".as_ptr(),
+        );
+        nn_defineMethod(
+            table,
+            c"media".as_ptr(),
+            true,
+            Some(disk_drive_media),
+            null_mut(),
+            c"media():string -- Return the internal floppy disk address.".as_ptr(),
+        );
+    }
+    unsafe { DISK_DRIVE_TABLE = table };
+}
+
+/// Adds an empty disk drive to the computer.
+///
+/// # Safety
+/// computer must be valid and init_disk_drive_table must have been called
+pub unsafe fn add_disk_drive(computer: *mut nn_computer, slot: i32) -> *mut DiskDrive {
+    let drive = Box::into_raw(Box::new(DiskDrive {
+        computer,
+        media: None,
+    }));
+    let component =
+        unsafe { nn_newComponent(computer, null_mut(), slot, DISK_DRIVE_TABLE, drive.cast()) };
+    if component.is_null() {
+        drop(unsafe { Box::from_raw(drive) });
+        return null_mut();
+    }
+    let info = DeviceInfo::new(c"disk", c"Floppy disk drive", c"Spinner 520S").vendor(SCUMMTECH);
+    unsafe { set_component_info(computer, component, info) };
+    drive
+}
@@ -0,0 +1,355 @@
+use std::{
+    ffi::{CStr, CString, c_void},
+    ops::Range,
+    ptr::null_mut,
+};
+
+use neonucleus::ffi::{
+    NN_VALUE_INT, NN_VALUE_NUMBER, nn_addHeat, nn_component, nn_componentTable, nn_computer,
+    nn_defineMethod, nn_getAllocator, nn_getArgument, nn_getUniverse, nn_newComponent,
+    nn_newComponentTable, nn_removeEnergy, nn_return, nn_setCError, nn_simulateBufferedIndirect,
+    nn_toString, nn_universe, nn_values_getType, nn_values_integer, nn_values_string,
+};
+
+use crate::devices::{DeviceInfo, set_component_info};
+
+const READ_SECTORS_PER_TICK: f64 = 16.0;
+const WRITE_SECTORS_PER_TICK: f64 = 8.0;
+const SEEK_SECTORS_PER_TICK: f64 = 128.0;
+
+const READ_HEAT_PER_SECTOR: f64 = 0.0015;
+const WRITE_HEAT_PER_SECTOR: f64 = 0.015;
+const MOTOR_HEAT_PER_SECTOR: f64 = 0.000005;
+
+const READ_ENERGY_PER_SECTOR: f64 = 0.015;
+const WRITE_ENERGY_PER_SECTOR: f64 = 0.03;
+const MOTOR_ENERGY_PER_SECTOR: f64 = 0.0005;
+
+/// OC's drive raises this for any sector or byte offset outside the disk.
+const INVALID_OFFSET: &CStr = c"invalid offset, not in a usable sector";
+
+static mut DRIVE_TABLE: *mut nn_componentTable = null_mut();
+
+/// Raw byte image behind an unmanaged drive component.
+pub struct Drive {
+    data: Vec<u8>,
+    sector_size: usize,
+    platters: usize,
+    label: Vec<u8>,
+    /// Scales how many sectors can be read, written and seeked past per tick.
+    speed: f64,
+    /// The sector of a platter the heads are over, which decides how far the next access seeks.
+    head: usize,
+}
+
+impl Drive {
+    /// `image` is copied to the start of a zeroed disk of `capacity` bytes.
+    pub fn new(image: &[u8], capacity: usize, sector_size: usize, platters: usize, speed: f64) -> Self {
+        let mut data = vec![0; capacity];
+        data[..image.len()].copy_from_slice(image);
+        Self {
+            data,
+            sector_size,
+            platters,
+            label: Vec::new(),
+            speed,
+            head: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The bytes of `sector`, numbered from 1 as in OC, or None if the disk has no such sector.
+    fn sector(&self, sector: i64) -> Option<Range<usize>> {
+        let index = usize::try_from(sector.checked_sub(1)?).ok()?;
+        let start = index.checked_mul(self.sector_size)?;
+        let end = start.checked_add(self.sector_size)?;
+        (end <= self.data.len()).then_some(start..end)
+    }
+
+    /// The index of byte `offset`, numbered from 1 as in OC, or None if it is past the end.
+    fn byte(&self, offset: i64) -> Option<usize> {
+        let index = usize::try_from(offset.checked_sub(1)?).ok()?;
+        (index < self.data.len()).then_some(index)
+    }
+
+    /// Moves the heads over the sector holding byte `index` and returns how many sectors they passed.
+    /// Every platter has the same layout, so only the position within a platter matters.
+    fn seek(&mut self, index: usize) -> usize {
+        let per_platter = (self.data.len() / self.sector_size / self.platters).max(1);
+        let target = index / self.sector_size % per_platter;
+        let distance = self.head.abs_diff(target);
+        self.head = target;
+        distance
+    }
+}
+
+unsafe fn drive<'a>(userdata: *mut c_void) -> &'a mut Drive {
+    unsafe { &mut *userdata.cast() }
+}
+
+/// Returns integer argument `idx`, or raises a Lua error.
+unsafe fn integer_arg(computer: *mut nn_computer, idx: usize) -> Option<i64> {
+    let value = unsafe { nn_getArgument(computer, idx) };
+    match unsafe { nn_values_getType(value) } as i32 {
+        NN_VALUE_INT => Some(unsafe { value.__bindgen_anon_1.integer }),
+        NN_VALUE_NUMBER => Some(unsafe { value.__bindgen_anon_1.number } as i64),
+        _ => {
+            unsafe { nn_setCError(computer, c"bad argument (number expected)".as_ptr()) };
+            None
+        }
+    }
+}
+
+/// Returns the bytes of string argument `idx`, or raises a Lua error.
+unsafe fn string_arg<'a>(computer: *mut nn_computer, idx: usize) -> Option<&'a [u8]> {
+    let mut len = 0;
+    let ptr = unsafe { nn_toString(nn_getArgument(computer, idx), &raw mut len) };
+    if ptr.is_null() {
+        unsafe { nn_setCError(computer, c"bad argument (string expected)".as_ptr()) };
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(ptr.cast(), len) })
+}
+
+unsafe fn return_bytes(computer: *mut nn_computer, bytes: &[u8]) {
+    let alloc = unsafe { nn_getAllocator(nn_getUniverse(computer)) };
+    unsafe { nn_return(computer, nn_values_string(alloc, bytes.as_ptr().cast(), bytes.len())) };
+}
+
+/// Seeks to byte `index` and charges one sector's worth of access against the tick's budget.
+unsafe fn access(
+    computer: *mut nn_computer,
+    component: *mut nn_component,
+    drive: &mut Drive,
+    index: usize,
+    write: bool,
+) {
+    let distance = drive.seek(index) as f64;
+    let (per_tick, heat, energy) = if write {
+        (WRITE_SECTORS_PER_TICK, WRITE_HEAT_PER_SECTOR, WRITE_ENERGY_PER_SECTOR)
+    } else {
+        (READ_SECTORS_PER_TICK, READ_HEAT_PER_SECTOR, READ_ENERGY_PER_SECTOR)
+    };
+    unsafe { nn_simulateBufferedIndirect(component, distance, SEEK_SECTORS_PER_TICK * drive.speed) };
+    unsafe { nn_simulateBufferedIndirect(component, 1.0, per_tick * drive.speed) };
+    unsafe { nn_addHeat(computer, heat + distance * MOTOR_HEAT_PER_SECTOR) };
+    unsafe { nn_removeEnergy(computer, energy + distance * MOTOR_ENERGY_PER_SECTOR) };
+}
+
+unsafe extern "C" fn drive_destroy(
+    _table_userdata: *mut c_void,
+    _component: *mut nn_component,
+    userdata: *mut c_void,
+) {
+    drop(unsafe { Box::from_raw(userdata.cast::<Drive>()) });
+}
+unsafe extern "C" fn drive_get_label(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    unsafe { return_bytes(computer, &drive.label) };
+}
+unsafe extern "C" fn drive_set_label(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    let Some(label) = (unsafe { string_arg(computer, 0) }) else {
+        return;
+    };
+    drive.label = label[..label.len().min(16)].to_vec();
+    unsafe { return_bytes(computer, &drive.label) };
+}
+unsafe extern "C" fn drive_get_capacity(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(drive.data.len() as i64)) };
+}
+unsafe extern "C" fn drive_get_sector_size(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(drive.sector_size as i64)) };
+}
+unsafe extern "C" fn drive_get_platter_count(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(drive.platters as i64)) };
+}
+unsafe extern "C" fn drive_read_sector(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    let Some(sector) = (unsafe { integer_arg(computer, 0) }) else {
+        return;
+    };
+    let Some(range) = drive.sector(sector) else {
+        return unsafe { nn_setCError(computer, INVALID_OFFSET.as_ptr()) };
+    };
+    unsafe { access(computer, component, drive, range.start, false) };
+    unsafe { return_bytes(computer, &drive.data[range]) };
+}
+/// Shorter data only overwrites the start of the sector, as in OC.
+unsafe extern "C" fn drive_write_sector(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    let Some(sector) = (unsafe { integer_arg(computer, 0) }) else {
+        return;
+    };
+    let Some(data) = (unsafe { string_arg(computer, 1) }) else {
+        return;
+    };
+    let Some(range) = drive.sector(sector) else {
+        return unsafe { nn_setCError(computer, INVALID_OFFSET.as_ptr()) };
+    };
+    unsafe { access(computer, component, drive, range.start, true) };
+    let len = data.len().min(range.len());
+    drive.data[range.start..range.start + len].copy_from_slice(&data[..len]);
+}
+/// Bytes are signed, as in OC.
+unsafe extern "C" fn drive_read_byte(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    let Some(offset) = (unsafe { integer_arg(computer, 0) }) else {
+        return;
+    };
+    let Some(index) = drive.byte(offset) else {
+        return unsafe { nn_setCError(computer, INVALID_OFFSET.as_ptr()) };
+    };
+    unsafe { access(computer, component, drive, index, false) };
+    unsafe { nn_return(computer, nn_values_integer(drive.data[index] as i8 as i64)) };
+}
+unsafe extern "C" fn drive_write_byte(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let drive = unsafe { drive(userdata) };
+    let Some(offset) = (unsafe { integer_arg(computer, 0) }) else {
+        return;
+    };
+    let Some(value) = (unsafe { integer_arg(computer, 1) }) else {
+        return;
+    };
+    let Some(index) = drive.byte(offset) else {
+        return unsafe { nn_setCError(computer, INVALID_OFFSET.as_ptr()) };
+    };
+    unsafe { access(computer, component, drive, index, true) };
+    drive.data[index] = value as u8;
+}
+
+type Method = unsafe extern "C" fn(*mut c_void, *mut c_void, *mut nn_component, *mut nn_computer);
+
+const METHODS: [(&CStr, Method, &CStr); 9] = [
+    (c"getLabel", drive_get_label, c"getLabel():string -- Get the current label of the drive."),
+    (c"setLabel", drive_set_label, c"setLabel(value:string):string -- Sets the label of the drive. Returns the new value, which may be truncated."),
+    (c"getCapacity", drive_get_capacity, c"getCapacity():number -- Returns the total capacity of the drive, in bytes."),
+    (c"getSectorSize", drive_get_sector_size, c"getSectorSize():number -- Returns the size of a single sector on the drive, in bytes."),
+    (c"getPlatterCount", drive_get_platter_count, c"getPlatterCount():number -- Returns the number of platters in the drive."),
+    (c"readSector", drive_read_sector, c"readSector(sector:number):string -- Read the current contents of the specified sector."),
+    (c"writeSector", drive_write_sector, c"writeSector(sector:number, value:string) -- Write the specified contents to the specified sector."),
+    (c"readByte", drive_read_byte, c"readByte(offset:number):number -- Read a single byte at the specified offset."),
+    (c"writeByte", drive_write_byte, c"writeByte(offset:number, value:number) -- Write a single byte to the specified offset."),
+];
+
+/// Registers the `drive` component type. Called once from init.
+pub fn init_drive_table(universe: *mut nn_universe) {
+    let alloc = unsafe { nn_getAllocator(universe) };
+    let table = unsafe {
+        nn_newComponentTable(alloc, c"drive".as_ptr(), null_mut(), None, Some(drive_destroy))
+    };
+    assert_ne!(table, null_mut());
+    // all direct, as in OC, with the sectors per tick limiting how fast they go
+    for (name, method, doc) in METHODS {
+        unsafe { nn_defineMethod(table, name.as_ptr(), true, Some(method), null_mut(), doc.as_ptr()) };
+    }
+    unsafe { DRIVE_TABLE = table };
+}
+
+/// Adds the drive to the computer, which takes ownership of it. Returns null if there is no room.
+///
+/// # Safety
+/// computer must be valid and init_drive_table must have been called
+pub unsafe fn add_drive_component(computer: *mut nn_computer, slot: i32, drive: Drive) -> *mut Drive {
+    let drive = Box::into_raw(Box::new(drive));
+    let component = unsafe { nn_newComponent(computer, null_mut(), slot, DRIVE_TABLE, drive.cast()) };
+    if component.is_null() {
+        drop(unsafe { Box::from_raw(drive) });
+        return null_mut();
+    }
+    let drive_ref = unsafe { &*drive };
+    let product = format!("Catfish {}L{}", drive_ref.data.len() / 1024, drive_ref.platters);
+    let info = DeviceInfo::new(c"disk", c"Hard disk drive", CString::new(product).unwrap())
+        .capacity(drive_ref.data.len())
+        .width(drive_ref.platters)
+        .clock((READ_SECTORS_PER_TICK * drive_ref.speed * 20.0) as usize);
+    unsafe { set_component_info(computer, component, info) };
+    drive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sectors_are_bounds_checked() {
+        let drive = Drive::new(&[], 4096, 512, 2, 1.0);
+        assert_eq!(drive.sector(1), Some(0..512));
+        assert_eq!(drive.sector(8), Some(3584..4096));
+        assert_eq!(drive.sector(9), None);
+        assert_eq!(drive.sector(0), None);
+        assert_eq!(drive.sector(-1), None);
+        assert_eq!(drive.sector(i64::MAX), None);
+        assert_eq!(drive.sector(i64::MIN), None);
+    }
+
+    #[test]
+    fn bytes_are_bounds_checked() {
+        let drive = Drive::new(&[7], 1024, 512, 1, 1.0);
+        assert_eq!(drive.byte(1), Some(0));
+        assert_eq!(drive.byte(1024), Some(1023));
+        assert_eq!(drive.byte(1025), None);
+        assert_eq!(drive.byte(0), None);
+        assert_eq!(drive.byte(i64::MIN), None);
+    }
+
+    #[test]
+    fn platters_share_a_head_position() {
+        let mut drive = Drive::new(&[], 4096, 512, 2, 1.0);
+        assert_eq!(drive.seek(3 * 512), 3);
+        // sector 7 is sector 3 of the second platter
+        assert_eq!(drive.seek(7 * 512), 0);
+        assert_eq!(drive.seek(0), 3);
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    ffi::{CStr, c_char, c_void},
+    ptr::null_mut,
+};
+
+use crate::{
+    devices::{DeviceInfo, set_component_info},
+    fields::{Field, define_fields},
+};
+
+use neonucleus::ffi::{
+    nn_addHeat, nn_component, nn_componentTable, nn_computer, nn_defineMethod, nn_getAllocator,
+    nn_getArgument, nn_getUniverse, nn_newComponent, nn_newComponentTable, nn_removeEnergy,
+    nn_return, nn_setCError, nn_simulateBufferedIndirect, nn_toString, nn_universe,
+    nn_values_boolean, nn_values_cstring, nn_values_integer, nn_values_nil, nn_values_string,
+};
+
+const BYTES_READ_PER_TICK: f64 = 32768.0;
+const BYTES_WRITTEN_PER_TICK: f64 = 4096.0;
+const READ_HEAT_PER_BYTE: f64 = 0.0015;
+const WRITE_HEAT_PER_BYTE: f64 = 0.03;
+const READ_ENERGY_PER_BYTE: f64 = 0.001;
+const WRITE_ENERGY_PER_BYTE: f64 = 0.05;
+
+#[link(wasm_import_module = "neoweb_eeprom")]
+unsafe extern "C" {
+    /// Tells the host that the EEPROM it registered as `id` was written to.
+    #[link_name = "changed"]
+    fn _changed(id: i32);
+}
+
+static mut EEPROM_TABLE: *mut nn_componentTable = null_mut();
+
+/// EEPROM contents, with OC's read-only lock and checksum.
+pub struct Eeprom {
+    code: Vec<u8>,
+    data: Vec<u8>,
+    label: Vec<u8>,
+    size: usize,
+    data_size: usize,
+    read_only: bool,
+    /// Passed to the host's `changed` import after every write, or -1 to not notify.
+    notify: i32,
+}
+
+impl Eeprom {
+    pub fn new(code: Vec<u8>, size: usize, data: Vec<u8>, data_size: usize, label: Vec<u8>, read_only: bool) -> Self {
+        Self {
+            code,
+            data,
+            label,
+            size,
+            data_size,
+            read_only,
+            notify: -1,
+        }
+    }
+
+    pub fn set_notify(&mut self, notify: i32) {
+        self.notify = notify;
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn label(&self) -> &[u8] {
+        &self.label
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn changed(&self) {
+        if self.notify >= 0 {
+            unsafe { _changed(self.notify) };
+        }
+    }
+
+    /// CRC-32 of the code as 8 hex digits. OC prints Guava's HashCode, which lists the bytes
+    /// least significant first.
+    pub fn checksum(&self) -> String {
+        format!("{:08x}", crc32(&self.code).swap_bytes())
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+unsafe fn eeprom<'a>(userdata: *mut c_void) -> &'a mut Eeprom {
+    unsafe { &mut *userdata.cast() }
+}
+
+/// Returns the bytes of string argument `idx`, or raises a Lua error.
+unsafe fn string_arg<'a>(computer: *mut nn_computer, idx: usize) -> Option<&'a [u8]> {
+    let mut len = 0;
+    let ptr = unsafe { nn_toString(nn_getArgument(computer, idx), &raw mut len) };
+    if ptr.is_null() {
+        unsafe { nn_setCError(computer, c"bad argument (string expected)".as_ptr()) };
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(ptr.cast(), len) })
+}
+
+unsafe fn return_bytes(computer: *mut nn_computer, bytes: &[u8]) {
+    let alloc = unsafe { nn_getAllocator(nn_getUniverse(computer)) };
+    unsafe { nn_return(computer, nn_values_string(alloc, bytes.as_ptr().cast(), bytes.len())) };
+}
+
+/// OC reports recoverable failures as `nil, reason`.
+unsafe fn return_failure(computer: *mut nn_computer, reason: &'static CStr) {
+    unsafe { nn_return(computer, nn_values_nil()) };
+    unsafe { nn_return(computer, nn_values_cstring(reason.as_ptr())) };
+}
+
+/// Spends the time, heat and energy of reading or writing `bytes`.
+unsafe fn charge(computer: *mut nn_computer, component: *mut nn_component, bytes: usize, write: bool) {
+    let (per_tick, heat, energy) = if write {
+        (BYTES_WRITTEN_PER_TICK, WRITE_HEAT_PER_BYTE, WRITE_ENERGY_PER_BYTE)
+    } else {
+        (BYTES_READ_PER_TICK, READ_HEAT_PER_BYTE, READ_ENERGY_PER_BYTE)
+    };
+    let bytes = bytes as f64;
+    unsafe { nn_simulateBufferedIndirect(component, bytes, per_tick) };
+    unsafe { nn_addHeat(computer, bytes * heat) };
+    unsafe { nn_removeEnergy(computer, bytes * energy) };
+}
+
+unsafe extern "C" fn eeprom_destroy(
+    _table_userdata: *mut c_void,
+    _component: *mut nn_component,
+    userdata: *mut c_void,
+) {
+    drop(unsafe { Box::from_raw(userdata.cast::<Eeprom>()) });
+}
+unsafe extern "C" fn eeprom_get(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { charge(computer, component, eeprom.code.len(), false) };
+    unsafe { return_bytes(computer, &eeprom.code) };
+}
+unsafe extern "C" fn eeprom_set(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    let Some(code) = (unsafe { string_arg(computer, 0) }) else {
+        return;
+    };
+    if eeprom.read_only {
+        return unsafe { return_failure(computer, c"storage is readonly") };
+    }
+    if code.len() > eeprom.size {
+        return unsafe { return_failure(computer, c"not enough space") };
+    }
+    unsafe { charge(computer, component, code.len(), true) };
+    eeprom.code = code.to_vec();
+    eeprom.changed();
+    unsafe { nn_return(computer, nn_values_boolean(true)) };
+}
+unsafe extern "C" fn eeprom_get_data(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { charge(computer, component, eeprom.data.len(), false) };
+    unsafe { return_bytes(computer, &eeprom.data) };
+}
+/// The data area stays writable on a read-only EEPROM, as in OC.
+unsafe extern "C" fn eeprom_set_data(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    let Some(data) = (unsafe { string_arg(computer, 0) }) else {
+        return;
+    };
+    if data.len() > eeprom.data_size {
+        return unsafe { return_failure(computer, c"not enough space") };
+    }
+    unsafe { charge(computer, component, data.len(), true) };
+    eeprom.data = data.to_vec();
+    eeprom.changed();
+    unsafe { nn_return(computer, nn_values_boolean(true)) };
+}
+unsafe extern "C" fn eeprom_get_label(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { return_bytes(computer, &eeprom.label) };
+}
+unsafe extern "C" fn eeprom_set_label(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    let Some(label) = (unsafe { string_arg(computer, 0) }) else {
+        return;
+    };
+    if eeprom.read_only {
+        return unsafe { return_failure(computer, c"storage is readonly") };
+    }
+    eeprom.label = label[..label.len().min(24)].to_vec();
+    eeprom.changed();
+    unsafe { return_bytes(computer, &eeprom.label) };
+}
+unsafe extern "C" fn eeprom_get_size(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(eeprom.size as i64)) };
+}
+unsafe extern "C" fn eeprom_get_data_size(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(eeprom.data_size as i64)) };
+}
+unsafe extern "C" fn eeprom_get_checksum(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { return_bytes(computer, eeprom.checksum().as_bytes()) };
+}
+unsafe extern "C" fn eeprom_readonly(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    unsafe { nn_return(computer, nn_values_boolean(eeprom.read_only)) };
+}
+unsafe extern "C" fn eeprom_make_readonly(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let eeprom = unsafe { eeprom(userdata) };
+    let Some(checksum) = (unsafe { string_arg(computer, 0) }) else {
+        return;
+    };
+    if checksum != eeprom.checksum().as_bytes() {
+        return unsafe { return_failure(computer, c"incorrect checksum") };
+    }
+    eeprom.read_only = true;
+    eeprom.changed();
+    unsafe { nn_return(computer, nn_values_boolean(true)) };
+}
+
+type Method = unsafe extern "C" fn(*mut c_void, *mut c_void, *mut nn_component, *mut nn_computer);
+
+const METHODS: [(&CStr, bool, Method, &CStr); 11] = [
+    (c"get", true, eeprom_get, c"get():string -- Get the currently stored byte array."),
+    (c"set", false, eeprom_set, c"set(data:string) -- Overwrite the currently stored byte array."),
+    (c"getData", true, eeprom_get_data, c"getData():string -- Get the currently stored byte array."),
+    (c"setData", false, eeprom_set_data, c"setData(data:string) -- Overwrite the currently stored byte array."),
+    (c"getLabel", true, eeprom_get_label, c"getLabel():string -- Get the label of the EEPROM."),
+    (c"setLabel", false, eeprom_set_label, c"setLabel(data:string):string -- Set the label of the EEPROM."),
+    (c"getSize", true, eeprom_get_size, c"getSize():number -- Get the storage capacity of this EEPROM."),
+    (c"getDataSize", true, eeprom_get_data_size, c"getDataSize():number -- Get the storage capacity of this EEPROM."),
+    (c"getChecksum", true, eeprom_get_checksum, c"getChecksum():string -- Get the checksum of the data on this EEPROM."),
+    (c"readonly", true, eeprom_readonly, c"boolean -- Whether the code and label of this EEPROM can no longer be changed."),
+    (c"makeReadonly", false, eeprom_make_readonly, c"makeReadonly(checksum:string):boolean -- Make this EEPROM readonly if it isn't already. This process cannot be reversed!"),
+];
+
+const FIELDS: &[Field] = &[Field {
+    name: c"readonly",
+    getter: true,
+    setter: false,
+}];
+
+/// Registers the `eeprom` component type. Called once from init.
+pub fn init_eeprom_table(universe: *mut nn_universe) {
+    let alloc = unsafe { nn_getAllocator(universe) };
+    let table = unsafe {
+        nn_newComponentTable(alloc, c"eeprom".as_ptr(), null_mut(), None, Some(eeprom_destroy))
+    };
+    assert_ne!(table, null_mut());
+    for (name, direct, method, doc) in METHODS {
+        unsafe { nn_defineMethod(table, name.as_ptr(), direct, Some(method), null_mut(), doc.as_ptr()) };
+    }
+    define_fields(table, FIELDS);
+    unsafe { EEPROM_TABLE = table };
+}
+
+/// Adds the EEPROM to the computer, which takes ownership of it.
+/// A null address gives it a random one.
+///
+/// # Safety
+/// computer must be valid, address must be null or a valid C string, and init_eeprom_table must have been called
+pub unsafe fn add_eeprom_component(
+    computer: *mut nn_computer,
+    address: *const c_char,
+    slot: i32,
+    eeprom: Eeprom,
+) -> *mut Eeprom {
+    let eeprom = Box::into_raw(Box::new(eeprom));
+    let component = unsafe {
+        nn_newComponent(computer, address.cast_mut(), slot, EEPROM_TABLE, eeprom.cast())
+    };
+    if component.is_null() {
+        drop(unsafe { Box::from_raw(eeprom) });
+        return null_mut();
+    }
+    let info = DeviceInfo::new(c"memory", c"EEPROM", c"FlashStick2k")
+        .capacity(unsafe { (*eeprom).size });
+    unsafe { set_component_info(computer, component, info) };
+    eeprom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn checksum_bytes_are_little_endian() {
+        let eeprom = Eeprom::new(b"123456789".to_vec(), 4096, Vec::new(), 256, Vec::new(), false);
+        assert_eq!(eeprom.checksum(), "2639f4cb");
+    }
+}
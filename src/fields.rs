@@ -0,0 +1,39 @@
+use std::ffi::{CStr, c_char};
+
+use neonucleus::ffi::nn_componentTable;
+
+/// A component field, which OC proxies expose as a plain value. It is backed by a method of the
+/// same name that returns the value when called with no arguments and sets it when given one,
+/// even if that one is nil.
+pub struct Field {
+    pub name: &'static CStr,
+    pub getter: bool,
+    pub setter: bool,
+}
+
+/// Fields by the component table that declared them, as neonucleus only knows about methods.
+/// Tables live as long as the universe, so entries are never removed.
+static mut FIELDS: Vec<(*mut nn_componentTable, &'static [Field])> = Vec::new();
+
+fn all() -> &'static mut Vec<(*mut nn_componentTable, &'static [Field])> {
+    unsafe { &mut *&raw mut FIELDS }
+}
+
+/// Marks methods already defined on `table` with nn_defineMethod as fields.
+pub fn define_fields(table: *mut nn_componentTable, fields: &'static [Field]) {
+    all().push((table, fields));
+}
+
+pub fn fields(table: *mut nn_componentTable) -> &'static [Field] {
+    all()
+        .iter()
+        .find(|(t, _)| *t == table)
+        .map_or(&[], |(_, fields)| fields)
+}
+
+/// # Safety
+/// name must be a valid C string
+pub unsafe fn is_field(table: *mut nn_componentTable, name: *const c_char) -> bool {
+    let name = unsafe { CStr::from_ptr(name) };
+    fields(table).iter().any(|field| field.name == name)
+}
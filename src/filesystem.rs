@@ -0,0 +1,98 @@
+use std::{
+    ffi::{CStr, CString, c_char},
+    ptr::null_mut,
+};
+
+use neonucleus::ffi::{
+    nn_Alloc, nn_alloc, nn_dealloc, nn_deallocStr, nn_error_write, nn_errorbuf_t, nn_strdup,
+};
+
+use crate::image::normalize;
+
+/// Labels are cut off at this many bytes.
+const MAX_LABEL: usize = 128;
+
+/// The normalized form of a path neonucleus passes to a filesystem table.
+/// # Safety
+/// path must be a valid C string
+pub unsafe fn path_arg(path: *const c_char) -> String {
+    normalize(&unsafe { CStr::from_ptr(path) }.to_string_lossy())
+}
+
+/// # Safety
+/// err must be the error buffer neonucleus passed in
+pub unsafe fn set_error(err: nn_errorbuf_t, msg: &CStr) {
+    unsafe { nn_error_write(err, msg.as_ptr()) };
+}
+
+/// Copies as much of `label` as fits into the `*buflen` bytes at buf, and sets buflen to the length copied.
+/// # Safety
+/// buf must be valid for `*buflen` bytes
+pub unsafe fn get_label(label: &[u8], buf: *mut c_char, buflen: *mut usize) {
+    let len = label.len().min(unsafe { *buflen });
+    unsafe {
+        buf.cast::<u8>()
+            .copy_from_nonoverlapping(label.as_ptr(), len)
+    };
+    unsafe { *buflen = len };
+}
+
+/// The label passed to setLabel, cut off at MAX_LABEL bytes.
+/// # Safety
+/// buf must be valid for buflen bytes
+pub unsafe fn label_arg<'a>(buf: *const c_char, buflen: usize) -> &'a [u8] {
+    unsafe { std::slice::from_raw_parts(buf.cast::<u8>(), buflen.min(MAX_LABEL)) }
+}
+
+/// Builds the array of names a filesystem's list returns, allocated with `alloc` as neonucleus frees it.
+/// # Safety
+/// alloc, len and err must be what neonucleus passed to list
+pub unsafe fn name_list<N: AsRef<[u8]>>(
+    alloc: *mut nn_Alloc,
+    names: &[N],
+    len: *mut usize,
+    err: nn_errorbuf_t,
+) -> *mut *mut c_char {
+    let list: *mut *mut c_char =
+        unsafe { nn_alloc(alloc, size_of::<*mut c_char>() * names.len()) }.cast();
+    if list.is_null() && !names.is_empty() {
+        unsafe { set_error(err, c"out of memory") };
+        return null_mut();
+    }
+    for (i, name) in names.iter().enumerate() {
+        let name = CString::new(name.as_ref()).unwrap_or_default();
+        let copy = unsafe { nn_strdup(alloc, name.as_ptr()) };
+        if copy.is_null() {
+            for j in 0..i {
+                unsafe { nn_deallocStr(alloc, *list.add(j)) };
+            }
+            let size = size_of::<*mut c_char>() * names.len();
+            unsafe { nn_dealloc(alloc, list.cast(), size) };
+            unsafe { set_error(err, c"out of memory") };
+            return null_mut();
+        }
+        unsafe { *list.add(i) = copy };
+    }
+    unsafe { *len = names.len() };
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_cut_off() {
+        let long = [b'a'; 200];
+        assert_eq!(
+            unsafe { label_arg(long.as_ptr().cast(), long.len()) }.len(),
+            MAX_LABEL
+        );
+
+        let mut buf = [0 as c_char; 4];
+        let mut len = buf.len();
+        unsafe { get_label(b"floppy", buf.as_mut_ptr(), &raw mut len) };
+        assert_eq!(len, 4);
+        assert_eq!(buf.map(|b| b as u8), *b"flop");
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    ffi::{CStr, c_char, c_int, c_void},
+    ptr::null_mut,
+};
+
+use neonucleus::ffi::{nn_Alloc, nn_errorbuf_t, nn_filesystemTable};
+
+use crate::filesystem::{label_arg, name_list, path_arg, set_error};
+
+// Every call names the host disk it targets. Paths are passed as pointer and length.
+// Calls are synchronous, so an async store like IndexedDB has to be cached on the host side.
+#[link(wasm_import_module = "neoweb_fs")]
+unsafe extern "C" {
+    /// Returns a file descriptor of 0 or more, or -1 if the file can't be opened.
+    #[link_name = "open"]
+    fn _open(disk: i32, path: *const u8, path_len: i32, mode: *const u8, mode_len: i32) -> i32;
+    #[link_name = "close"]
+    fn _close(disk: i32, fd: i32) -> i32;
+    /// Returns the number of bytes read, 0 at the end of the file, or -1 on error.
+    #[link_name = "read"]
+    fn _read(disk: i32, fd: i32, buf: *mut u8, len: i32) -> i32;
+    #[link_name = "write"]
+    fn _write(disk: i32, fd: i32, buf: *const u8, len: i32) -> i32;
+    /// `whence` is 0 for set, 1 for cur and 2 for end. Returns the new position or -1.
+    #[link_name = "seek"]
+    fn _seek(disk: i32, fd: i32, whence: i32, offset: i32) -> i32;
+    /// Writes the NUL-separated entries of a directory into buf, directories ending in `/`.
+    /// Returns the full length needed, which may exceed len, or -1 if it isn't a directory.
+    #[link_name = "list"]
+    fn _list(disk: i32, path: *const u8, path_len: i32, buf: *mut u8, len: i32) -> i32;
+    /// Returns -1 if nothing exists at path, 0 for a file and 1 for a directory.
+    #[link_name = "stat"]
+    fn _stat(disk: i32, path: *const u8, path_len: i32) -> i32;
+    #[link_name = "size"]
+    fn _size(disk: i32, path: *const u8, path_len: i32) -> i32;
+    /// Milliseconds since the unix epoch.
+    #[link_name = "last_modified"]
+    fn _last_modified(disk: i32, path: *const u8, path_len: i32) -> f64;
+    #[link_name = "make_directory"]
+    fn _make_directory(disk: i32, path: *const u8, path_len: i32) -> i32;
+    #[link_name = "remove"]
+    fn _remove(disk: i32, path: *const u8, path_len: i32) -> i32;
+    #[link_name = "rename"]
+    fn _rename(disk: i32, from: *const u8, from_len: i32, to: *const u8, to_len: i32) -> i32;
+    #[link_name = "space_used"]
+    fn _space_used(disk: i32) -> i32;
+    /// Writes at most len bytes of the disk's label into buf and returns how many it wrote.
+    #[link_name = "get_label"]
+    fn _get_label(disk: i32, buf: *mut u8, len: i32) -> i32;
+    /// Stores the label with the disk, so it survives the page. Returns -1 if it can't be stored.
+    #[link_name = "set_label"]
+    fn _set_label(disk: i32, buf: *const u8, len: i32) -> i32;
+}
+
+/// A filesystem whose storage, label included, lives entirely on the host, identified by a disk number.
+pub struct HostFs {
+    disk: i32,
+    capacity: usize,
+    read_only: bool,
+}
+
+impl HostFs {
+    pub fn new(disk: i32, capacity: usize, read_only: bool) -> Self {
+        Self {
+            disk,
+            capacity,
+            read_only,
+        }
+    }
+}
+
+unsafe fn host<'a>(userdata: *mut c_void) -> &'a mut HostFs {
+    unsafe { &mut *userdata.cast() }
+}
+fn stat(disk: i32, path: &str) -> i32 {
+    unsafe { _stat(disk, path.as_ptr(), path.len() as i32) }
+}
+
+/// How many bytes writing `len` bytes at fd's position adds to the file, found by seeking to the
+/// end and back. Hosts keep an appending descriptor at the end, so this holds for appends too.
+/// Returns None if fd is not open.
+fn growth(disk: i32, fd: i32, len: usize) -> Option<usize> {
+    let position = unsafe { _seek(disk, fd, 1, 0) };
+    let end = unsafe { _seek(disk, fd, 2, 0) };
+    if position < 0 || end < 0 || unsafe { _seek(disk, fd, 0, position) } < 0 {
+        return None;
+    }
+    Some((position as usize + len).saturating_sub(end as usize))
+}
+
+unsafe extern "C" fn fs_deinit(userdata: *mut c_void) {
+    drop(unsafe { Box::from_raw(userdata.cast::<HostFs>()) });
+}
+unsafe extern "C" fn fs_get_label(userdata: *mut c_void, buf: *mut c_char, buflen: *mut usize) {
+    let fs = unsafe { host(userdata) };
+    let len = unsafe { *buflen }.min(i32::MAX as usize);
+    let written = unsafe { _get_label(fs.disk, buf.cast(), len as i32) };
+    unsafe { *buflen = (written.max(0) as usize).min(len) };
+}
+unsafe extern "C" fn fs_set_label(userdata: *mut c_void, buf: *const c_char, buflen: usize) -> usize {
+    let fs = unsafe { host(userdata) };
+    let label = unsafe { label_arg(buf, buflen) };
+    if unsafe { _set_label(fs.disk, label.as_ptr(), label.len() as i32) } < 0 {
+        return 0;
+    }
+    label.len()
+}
+unsafe extern "C" fn fs_space_used(userdata: *mut c_void) -> usize {
+    let fs = unsafe { host(userdata) };
+    unsafe { _space_used(fs.disk) }.max(0) as usize
+}
+unsafe extern "C" fn fs_is_read_only(userdata: *mut c_void) -> bool {
+    unsafe { host(userdata) }.read_only
+}
+unsafe extern "C" fn fs_size(userdata: *mut c_void, path: *const c_char, _err: nn_errorbuf_t) -> usize {
+    let fs = unsafe { host(userdata) };
+    let path = unsafe { path_arg(path) };
+    unsafe { _size(fs.disk, path.as_ptr(), path.len() as i32) }.max(0) as usize
+}
+unsafe extern "C" fn fs_remove(userdata: *mut c_void, path: *const c_char, err: nn_errorbuf_t) -> usize {
+    let fs = unsafe { host(userdata) };
+    if fs.read_only {
+        unsafe { set_error(err, c"filesystem is read-only") };
+        return 0;
+    }
+    let path = unsafe { path_arg(path) };
+    (unsafe { _remove(fs.disk, path.as_ptr(), path.len() as i32) } >= 0) as usize
+}
+unsafe extern "C" fn fs_last_modified(
+    userdata: *mut c_void,
+    path: *const c_char,
+    _err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { host(userdata) };
+    let path = unsafe { path_arg(path) };
+    // the host reports milliseconds, like JavaScript dates; the rest of neoweb uses seconds
+    (unsafe { _last_modified(fs.disk, path.as_ptr(), path.len() as i32) } / 1000.0).max(0.0) as usize
+}
+unsafe extern "C" fn fs_rename(
+    userdata: *mut c_void,
+    from: *const c_char,
+    to: *const c_char,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { host(userdata) };
+    if fs.read_only {
+        unsafe { set_error(err, c"filesystem is read-only") };
+        return 0;
+    }
+    let from = unsafe { path_arg(from) };
+    let to = unsafe { path_arg(to) };
+    let res = unsafe {
+        _rename(
+            fs.disk,
+            from.as_ptr(),
+            from.len() as i32,
+            to.as_ptr(),
+            to.len() as i32,
+        )
+    };
+    if res < 0 {
+        unsafe { set_error(err, c"no such file or directory") };
+        return 0;
+    }
+    1
+}
+unsafe extern "C" fn fs_exists(userdata: *mut c_void, path: *const c_char, _err: nn_errorbuf_t) -> bool {
+    let fs = unsafe { host(userdata) };
+    stat(fs.disk, &unsafe { path_arg(path) }) >= 0
+}
+unsafe extern "C" fn fs_is_directory(
+    userdata: *mut c_void,
+    path: *const c_char,
+    _err: nn_errorbuf_t,
+) -> bool {
+    let fs = unsafe { host(userdata) };
+    stat(fs.disk, &unsafe { path_arg(path) }) == 1
+}
+unsafe extern "C" fn fs_make_directory(
+    userdata: *mut c_void,
+    path: *const c_char,
+    err: nn_errorbuf_t,
+) -> bool {
+    let fs = unsafe { host(userdata) };
+    if fs.read_only {
+        unsafe { set_error(err, c"filesystem is read-only") };
+        return false;
+    }
+    let path = unsafe { path_arg(path) };
+    unsafe { _make_directory(fs.disk, path.as_ptr(), path.len() as i32) } > 0
+}
+unsafe extern "C" fn fs_list(
+    alloc: *mut nn_Alloc,
+    userdata: *mut c_void,
+    path: *const c_char,
+    len: *mut usize,
+    err: nn_errorbuf_t,
+) -> *mut *mut c_char {
+    let fs = unsafe { host(userdata) };
+    let path = unsafe { path_arg(path) };
+
+    let mut buf: Vec<u8> = Vec::new();
+    loop {
+        let needed = unsafe {
+            _list(
+                fs.disk,
+                path.as_ptr(),
+                path.len() as i32,
+                buf.as_mut_ptr(),
+                buf.len() as i32,
+            )
+        };
+        if needed < 0 {
+            unsafe { set_error(err, c"no such directory") };
+            return null_mut();
+        }
+        if needed as usize <= buf.len() {
+            buf.truncate(needed as usize);
+            break;
+        }
+        buf.resize(needed as usize, 0);
+    }
+
+    let names: Vec<&[u8]> = buf.split(|&b| b == 0).filter(|name| !name.is_empty()).collect();
+    unsafe { name_list(alloc, &names, len, err) }
+}
+/// neonucleus takes fd 0 for a failed open, so the host's descriptors, which may be 0, are
+/// shifted up by one on the way out.
+fn host_fd(fd: usize) -> Option<i32> {
+    i32::try_from(fd.checked_sub(1)?).ok()
+}
+
+unsafe extern "C" fn fs_open(
+    userdata: *mut c_void,
+    path: *const c_char,
+    mode: *const c_char,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { host(userdata) };
+    let mode = unsafe { CStr::from_ptr(mode) }.to_bytes();
+    if fs.read_only && (mode.contains(&b'w') || mode.contains(&b'a')) {
+        unsafe { set_error(err, c"filesystem is read-only") };
+        return 0;
+    }
+    let path = unsafe { path_arg(path) };
+    let fd = unsafe {
+        _open(
+            fs.disk,
+            path.as_ptr(),
+            path.len() as i32,
+            mode.as_ptr(),
+            mode.len() as i32,
+        )
+    };
+    if fd < 0 {
+        unsafe { set_error(err, c"no such file") };
+        return 0;
+    }
+    fd as usize + 1
+}
+unsafe extern "C" fn fs_close(userdata: *mut c_void, fd: usize, err: nn_errorbuf_t) -> bool {
+    let fs = unsafe { host(userdata) };
+    let closed = host_fd(fd).is_some_and(|fd| unsafe { _close(fs.disk, fd) } >= 0);
+    if !closed {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return false;
+    }
+    true
+}
+unsafe extern "C" fn fs_write(
+    userdata: *mut c_void,
+    fd: usize,
+    buf: *const c_char,
+    len: usize,
+    err: nn_errorbuf_t,
+) -> bool {
+    let fs = unsafe { host(userdata) };
+    let Some(fd) = host_fd(fd) else {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return false;
+    };
+    let Some(grown) = growth(fs.disk, fd, len) else {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return false;
+    };
+    let used = unsafe { _space_used(fs.disk) }.max(0) as usize;
+    if used + grown > fs.capacity {
+        unsafe { set_error(err, c"not enough space") };
+        return false;
+    }
+    if unsafe { _write(fs.disk, fd, buf.cast(), len as i32) } < 0 {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return false;
+    }
+    true
+}
+unsafe extern "C" fn fs_read(
+    userdata: *mut c_void,
+    fd: usize,
+    buf: *mut c_char,
+    required: usize,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { host(userdata) };
+    let Some(fd) = host_fd(fd) else {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return 0;
+    };
+    let required = required.min(i32::MAX as usize);
+    let read = unsafe { _read(fs.disk, fd, buf.cast(), required as i32) };
+    if read < 0 {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return 0;
+    }
+    (read as usize).min(required)
+}
+unsafe extern "C" fn fs_seek(
+    userdata: *mut c_void,
+    fd: usize,
+    whence: *const c_char,
+    off: c_int,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { host(userdata) };
+    let whence = match unsafe { CStr::from_ptr(whence) }.to_bytes() {
+        b"set" => 0,
+        b"cur" => 1,
+        b"end" => 2,
+        _ => {
+            unsafe { set_error(err, c"invalid mode") };
+            return 0;
+        }
+    };
+    let Some(fd) = host_fd(fd) else {
+        unsafe { set_error(err, c"bad file descriptor") };
+        return 0;
+    };
+    let position = unsafe { _seek(fs.disk, fd, whence, off) };
+    if position < 0 {
+        unsafe { set_error(err, c"invalid offset") };
+        return 0;
+    }
+    position as usize
+}
+
+/// Builds the neonucleus filesystem table for a host disk. The table owns `fs` and frees it on deinit.
+pub fn host_table(fs: *mut HostFs) -> nn_filesystemTable {
+    nn_filesystemTable {
+        userdata: fs.cast(),
+        deinit: Some(fs_deinit),
+        getLabel: Some(fs_get_label),
+        setLabel: Some(fs_set_label),
+        spaceUsed: Some(fs_space_used),
+        spaceTotal: unsafe { (*fs).capacity },
+        isReadOnly: Some(fs_is_read_only),
+        size: Some(fs_size),
+        remove: Some(fs_remove),
+        lastModified: Some(fs_last_modified),
+        rename: Some(fs_rename),
+        exists: Some(fs_exists),
+        isDirectory: Some(fs_is_directory),
+        makeDirectory: Some(fs_make_directory),
+        list: Some(fs_list),
+        open: Some(fs_open),
+        close: Some(fs_close),
+        write: Some(fs_write),
+        read: Some(fs_read),
+        seek: Some(fs_seek),
+    }
+}
@@ -0,0 +1,275 @@
+use std::{collections::BTreeMap, ffi::CStr};
+
+use neotar::Deserialize;
+
+use crate::{archive, compression, context::get_time};
+
+/// A filesystem image held in wasm memory, shared read-only between any number of overlays.
+/// `modified` is in seconds since the unix epoch, like the context clock.
+pub enum Node {
+    File { data: Vec<u8>, modified: u64 },
+    Directory { children: BTreeMap<String, Node>, modified: u64 },
+}
+
+impl Node {
+    /// Detects the image format (neotar, ustar/pax tar or zip, optionally gzip or zstd compressed) and reads it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Node, &'static CStr> {
+        Node::sections_from_bytes(bytes)?
+            .into_iter()
+            .next()
+            .ok_or(c"neotar file has no sections")
+    }
+
+    /// Like [`Node::from_bytes`], but returns every section of a neotar file.
+    /// Other formats always have exactly one section.
+    pub fn sections_from_bytes(bytes: &[u8]) -> Result<Vec<Node>, &'static CStr> {
+        let bytes = compression::decompress(bytes)?;
+        if archive::is_zip(&bytes) {
+            Ok(vec![archive::read_zip(&bytes)?])
+        } else if archive::is_tar(&bytes) {
+            Ok(vec![archive::read_tar(&bytes)?])
+        } else {
+            Node::neotar_sections(&bytes)
+        }
+    }
+
+    /// neotar stores no times, so every entry is stamped with the time the image was loaded.
+    fn neotar_sections(bytes: &[u8]) -> Result<Vec<Node>, &'static CStr> {
+        // neotar panics on malformed input, which would take every computer down with it
+        validate_neotar(bytes)?;
+        let file = neotar::File::read(bytes).0;
+        file.sanity_check();
+        let now = get_time() as u64;
+        Ok(file
+            .sections
+            .iter()
+            .map(|section| {
+                let (entries, root_len) = neotar::files::read_entries_recursive(section);
+                Node::Directory {
+                    children: read_children(&entries, &mut 0, root_len as usize, now),
+                    modified: now,
+                }
+            })
+            .collect())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        match self {
+            Node::Directory { children, .. } => children.get(name),
+            Node::File { .. } => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Node::File { data, .. } => data.len(),
+            Node::Directory { children, .. } => children.values().map(Node::size).sum(),
+        }
+    }
+
+    pub fn modified(&self) -> u64 {
+        match self {
+            Node::File { modified, .. } | Node::Directory { modified, .. } => *modified,
+        }
+    }
+}
+
+const NEOTAR_HEADER: usize = 16;
+const NEOTAR_MAX_DEPTH: usize = 64;
+
+fn read_be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Walks the whole neotar layout, checking every length against the buffer.
+/// A file is a 16 byte header followed by sections, each a big-endian length and a root entry count.
+fn validate_neotar(bytes: &[u8]) -> Result<(), &'static CStr> {
+    if bytes.len() < NEOTAR_HEADER || !bytes.starts_with(b"ntar") {
+        return Err(c"unrecognized filesystem image format");
+    }
+    let mut pos = NEOTAR_HEADER;
+    if pos == bytes.len() {
+        return Err(c"neotar file has no sections");
+    }
+    while pos < bytes.len() {
+        let len = read_be_u32(bytes, pos).ok_or(c"truncated neotar section")? as usize;
+        pos += 4;
+        let section = pos
+            .checked_add(len)
+            .and_then(|end| bytes.get(pos..end))
+            .ok_or(c"truncated neotar section")?;
+        let count = read_be_u32(section, 0).ok_or(c"truncated neotar section")?;
+        validate_entries(section, 4, count, 0)?;
+        pos += len;
+    }
+    Ok(())
+}
+
+/// Each entry is a kind byte (0 file, 1 directory), a NUL-terminated name and a big-endian
+/// length, which is the byte count of a file or the entry count of a directory.
+fn validate_entries(
+    section: &[u8],
+    mut pos: usize,
+    count: u32,
+    depth: usize,
+) -> Result<usize, &'static CStr> {
+    if depth > NEOTAR_MAX_DEPTH {
+        return Err(c"neotar directories nested too deeply");
+    }
+    for _ in 0..count {
+        let kind = *section.get(pos).ok_or(c"truncated neotar entry")?;
+        let name_len = section
+            .get(pos + 1..)
+            .and_then(|rest| rest.iter().position(|&b| b == 0))
+            .ok_or(c"truncated neotar entry")?;
+        pos += name_len + 2;
+        let len = read_be_u32(section, pos).ok_or(c"truncated neotar entry")?;
+        pos += 4;
+        pos = match kind {
+            0 => pos
+                .checked_add(len as usize)
+                .filter(|&end| end <= section.len())
+                .ok_or(c"truncated neotar file")?,
+            1 => validate_entries(section, pos, len, depth + 1)?,
+            _ => return Err(c"unknown neotar entry kind"),
+        };
+    }
+    Ok(pos)
+}
+
+fn read_children(
+    entries: &[neotar::files::Entry],
+    pos: &mut usize,
+    len: usize,
+    modified: u64,
+) -> BTreeMap<String, Node> {
+    let mut children = BTreeMap::new();
+    for _ in 0..len {
+        let Some(entry) = entries.get(*pos) else {
+            break;
+        };
+        *pos += 1;
+        let name = entry.name.to_string_lossy().into_owned();
+        let node = match entry.entry {
+            neotar::files::EntryInner::File(contents) => Node::File {
+                data: contents.to_vec(),
+                modified,
+            },
+            neotar::files::EntryInner::Directory(len) => Node::Directory {
+                children: read_children(entries, pos, len as usize, modified),
+                modified,
+            },
+        };
+        children.insert(name, node);
+    }
+    children
+}
+
+/// Turns an OC path into the `/`-separated key used by the overlay, resolving `.` and `..`.
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: u8, name: &str, len: u32) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(&len.to_be_bytes());
+        out
+    }
+
+    fn neotar(sections: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = b"ntar".to_vec();
+        out.resize(NEOTAR_HEADER, 0);
+        for section in sections {
+            out.extend_from_slice(&(section.len() as u32).to_be_bytes());
+            out.extend_from_slice(section);
+        }
+        out
+    }
+
+    /// A section with `dir/file` holding "hi" and an empty `top`.
+    fn section() -> Vec<u8> {
+        let mut section = 2u32.to_be_bytes().to_vec();
+        section.extend(entry(1, "dir", 1));
+        section.extend(entry(0, "file", 2));
+        section.extend_from_slice(b"hi");
+        section.extend(entry(0, "top", 0));
+        section
+    }
+
+    #[test]
+    fn valid_neotar() {
+        assert_eq!(validate_neotar(&neotar(&[section(), section()])), Ok(()));
+    }
+
+    #[test]
+    fn neotar_without_sections() {
+        assert!(validate_neotar(&neotar(&[])).is_err());
+        assert!(validate_neotar(b"ntar").is_err());
+        assert!(validate_neotar(b"not a neotar file").is_err());
+    }
+
+    #[test]
+    fn truncated_neotar() {
+        let bytes = neotar(&[section()]);
+        for len in NEOTAR_HEADER + 1..bytes.len() {
+            assert!(validate_neotar(&bytes[..len]).is_err(), "{len}");
+        }
+        // the section length claims more than there is
+        let mut bytes = bytes;
+        bytes[NEOTAR_HEADER..NEOTAR_HEADER + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(validate_neotar(&bytes).is_err());
+    }
+
+    #[test]
+    fn neotar_entry_lengths_are_checked() {
+        let mut section = 1u32.to_be_bytes().to_vec();
+        section.extend(entry(0, "file", u32::MAX));
+        assert!(validate_neotar(&neotar(&[section])).is_err());
+
+        let mut section = 1u32.to_be_bytes().to_vec();
+        section.extend(entry(1, "dir", u32::MAX));
+        assert!(validate_neotar(&neotar(&[section])).is_err());
+
+        let mut section = 1u32.to_be_bytes().to_vec();
+        section.extend(entry(7, "what", 0));
+        assert!(validate_neotar(&neotar(&[section])).is_err());
+
+        // a name that runs to the end without a terminator
+        let mut section = 1u32.to_be_bytes().to_vec();
+        section.extend_from_slice(b"\0name");
+        assert!(validate_neotar(&neotar(&[section])).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_neotar() {
+        let mut section = 1u32.to_be_bytes().to_vec();
+        for _ in 0..=NEOTAR_MAX_DEPTH + 1 {
+            section.extend(entry(1, "d", 1));
+        }
+        section.extend(entry(0, "f", 0));
+        assert!(validate_neotar(&neotar(&[section])).is_err());
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize("/a//b/./c/"), "a/b/c");
+        assert_eq!(normalize("a/../../b"), "b");
+        assert_eq!(normalize("/"), "");
+    }
+}
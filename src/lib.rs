@@ -1,20 +1,53 @@
 use core::slice;
 use std::{
-    alloc::{alloc, dealloc, Layout}, ptr::null_mut
+    alloc::{alloc, dealloc, Layout}, collections::BTreeMap, ffi::{c_char, CStr, CString}, ptr::{null, null_mut}, rc::Rc
 };
 
 use neonucleus::ffi::{
-    nn_addEEPROM, nn_addFileSystem, nn_addGPU, nn_addKeyboard, nn_addScreen, nn_architecture, nn_computer, nn_eepromControl, nn_filesystemControl, nn_getComputerUserData, nn_getDepth, nn_getError, nn_getPixel, nn_getTemperature, nn_gpuControl, nn_isOn, nn_isOverheating, nn_loadCoreComponentTables, nn_mapDepth, nn_mountKeyboard, nn_newComputer, nn_newScreen, nn_pushSignal, nn_removeHeat, nn_scrchr_t, nn_screen, nn_setDepth, nn_setEnergyInfo, nn_setPixel, nn_tickComputer, nn_universe, nn_value, nn_values_cstring, nn_values_integer, nn_veepromOptions, nn_vfilesystemImageNode, nn_vfilesystemOptions, nn_volatileEEPROM, nn_volatileFilesystem, NN_STATE_BLACKOUT, NN_STATE_CLOSING, NN_STATE_REPEAT, NN_STATE_SWITCH
+    nn_addFileSystem, nn_addGPU, nn_addKeyboard, nn_addScreen, nn_addSupportedArchitecture, nn_architecture, nn_clearError, nn_component, nn_computer, nn_destroyFilesystem, nn_filesystem, nn_filesystemControl, nn_findComponent, nn_getComponentAddress, nn_getComputerAddress, nn_getComputerUserData, nn_getDepth, nn_getError, nn_getNextArchitecture, nn_getPixel, nn_getTemperature, nn_getTmpAddress, nn_gpuControl, nn_isOn, nn_isOverheating, nn_loadCoreComponentTables, nn_mapDepth, nn_mountKeyboard, nn_newComputer, nn_newFilesystem, nn_newScreen, nn_pushSignal, nn_removeComponent, nn_removeHeat, nn_scrchr_t, nn_screen, nn_setDepth, nn_setEnergyInfo, nn_setPixel, nn_swapArchitecture, nn_tickComputer, nn_universe, nn_value, nn_values_cstring, nn_values_integer, nn_vfilesystemOptions, nn_volatileFilesystem, NN_STATE_BLACKOUT, NN_STATE_CLOSING, NN_STATE_REPEAT, NN_STATE_SWITCH
 };
-use neotar::Deserialize;
 
 use crate::arch::ARCH_TABLE;
-use crate::context::{get_context, init_random};
+use crate::compression::decompress;
+use crate::context::{
+    advance_clock, get_context, get_time, init_random, random_address, set_deterministic,
+    deterministic, resume_wall_clock, Deterministic,
+};
+use crate::devices::{remove_device_info, set_component_info, set_device_info, DeviceInfo, Devices, FILESYSTEM};
+use crate::diskdrive::{add_disk_drive, init_disk_drive_table, DiskDrive};
+use crate::drive::{add_drive_component, init_drive_table, Drive};
+use crate::eeprom::{add_eeprom_component, init_eeprom_table, Eeprom};
+use crate::hostfs::{host_table, HostFs};
+use crate::image::Node;
+use crate::overlay::{overlay_table, Overlay};
+#[cfg(feature = "probe")]
+use crate::probe::{add_probe_component, init_probe_table};
+use crate::replay::{Input, Recording, Replay};
+use crate::value::Values;
 
 mod arch;
+mod archive;
+mod compression;
 mod context;
+mod devices;
+mod diskdrive;
+mod drive;
+mod eeprom;
+mod fields;
+mod filesystem;
+mod hostfs;
+mod image;
+mod overlay;
+#[cfg(feature = "probe")]
+mod probe;
+mod replay;
+mod value;
 
 const LEGACY_COLORS: bool = true;
+const MEMORY_SIZE: usize = 1024 * 1024 * 2;
+const COMPONENT_LIMIT: usize = 16;
+/// Installed by new_computer, until the host calls load_eeprom.
+const DEFAULT_BIOS: &[u8] = include_bytes!("bios.lua");
 
 #[link(wasm_import_module = "neoweb_console")]
 unsafe extern "C" {
@@ -32,6 +65,45 @@ fn set_cell(id: usize, x: usize, y: usize, ch: char, fg: i32, bg: i32) {
 }
 
 static mut UNIVERSE: *mut nn_universe = null_mut();
+static mut LAST_ERROR: &CStr = c"";
+/// Computers created so far. None are ever destroyed.
+static mut COMPUTERS: usize = 0;
+
+/// Per-computer host state, stored as the computer's userdata.
+struct Machine {
+    slot: i32,
+    tmp_size: usize,
+    /// Address and slot of the built-in BIOS, until load_eeprom replaces it.
+    bios: Option<(CString, i32)>,
+    /// Direct-call budget per tick, set by the CPU tier.
+    call_budget: f64,
+    /// Lua instructions per tick before the machine is preempted.
+    instructions_per_tick: u64,
+    /// Lua instructions without yielding before the machine errors.
+    instruction_timeout: u64,
+    /// The CPU is no component, so it has no address from neonucleus.
+    cpu_address: CString,
+    /// Calls to tick so far, which recorded inputs are stamped with.
+    ticks: u64,
+    /// In the order they were added, so recordings can refer to them by index.
+    disk_drives: Vec<*mut DiskDrive>,
+    /// Filesystems backed by the host, which recordings can't capture.
+    host_disks: usize,
+    /// Both start before the first tick, so ticks counts from the start of either.
+    recording: Option<Recording>,
+    /// The inputs left to apply.
+    replay: Option<Replay>,
+    values: Values,
+    devices: Devices,
+    /// Set once the machine stopped on an error, until the host ticks it again.
+    halted: bool,
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn machine<'a>(computer: *mut nn_computer) -> &'a mut Machine {
+    unsafe { &mut *nn_getComputerUserData(computer).cast() }
+}
 
 #[unsafe(no_mangle)]
 pub extern "C" fn init() {
@@ -42,11 +114,55 @@ pub extern "C" fn init() {
     let universe = unsafe { neonucleus::ffi::nn_newUniverse(get_context()) };
     assert_ne!(universe, null_mut());
     unsafe { nn_loadCoreComponentTables(universe) };
+    init_disk_drive_table(universe);
+    init_drive_table(universe);
+    init_eeprom_table(universe);
+    #[cfg(feature = "probe")]
+    init_probe_table(universe);
     unsafe { UNIVERSE = universe };
 }
 
+/// Makes runs reproducible: seeds the RNG and replaces the wall clock with one that starts at
+/// `start` seconds since the epoch and advances `step` seconds per tick.
+/// Call after init and before creating any computer, so their addresses are reproducible too.
+/// The clock and RNG are shared, so this fails once any computer exists, and new_computer
+/// fails once there is one.
+/// Returns 0 on success or -1 on failure, see last_error.
+#[unsafe(no_mangle)]
+pub extern "C" fn enable_deterministic(seed: u64, start: f64, step: f64) -> i32 {
+    if unsafe { COMPUTERS } > 0 {
+        return fail(c"deterministic mode must be enabled before creating a computer");
+    }
+    set_deterministic(Deterministic { seed, start, step });
+    0
+}
+
+/// The current time in seconds since the epoch, which is virtual in deterministic mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn current_time() -> f64 {
+    get_time()
+}
+
+/// Continues the clock from `time`, the current_time a snapshot was taken at, so uptime and
+/// timers don't jump by however long it was stored. Call right after restoring the snapshot.
+#[unsafe(no_mangle)]
+pub extern "C" fn resume_clock(time: f64) {
+    resume_wall_clock(time);
+}
+
+/// Creates a computer with a GPU, the built-in BIOS and a volatile tmpfs of `tmp_size` bytes at its tmp address.
+/// Returns null if tmp_size is negative or a second computer would share the deterministic clock,
+/// see last_error.
 #[unsafe(no_mangle)]
-pub extern "C" fn new_computer() -> *mut nn_computer {
+pub extern "C" fn new_computer(tmp_size: i32) -> *mut nn_computer {
+    if tmp_size < 0 {
+        fail(c"tmpfs size must not be negative");
+        return null_mut();
+    }
+    if deterministic().is_some() && unsafe { COMPUTERS } > 0 {
+        fail(c"deterministic mode allows only one computer");
+        return null_mut();
+    }
     let universe = unsafe { UNIVERSE };
     assert_ne!(universe, null_mut());
     let computer = unsafe {
@@ -54,12 +170,31 @@ pub extern "C" fn new_computer() -> *mut nn_computer {
             universe,
             c"test".as_ptr().cast_mut(),
             (&ARCH_TABLE as *const nn_architecture).cast_mut(),
-            Box::into_raw(Box::new(1_i32)).cast(),
-            1024 * 1024 * 2,
-            16,
+            Box::into_raw(Box::new(Machine {
+                slot: 1,
+                tmp_size: tmp_size as usize,
+                bios: None,
+                call_budget: 1.5,
+                instructions_per_tick: 1_000_000,
+                instruction_timeout: 100_000_000,
+                cpu_address: random_address(),
+                ticks: 0,
+                disk_drives: Vec::new(),
+                host_disks: 0,
+                recording: None,
+                replay: None,
+                values: Values::default(),
+                devices: Devices::default(),
+                halted: false,
+            }))
+            .cast(),
+            MEMORY_SIZE,
+            COMPONENT_LIMIT,
         )
     };
     assert_ne!(computer, null_mut());
+    unsafe { COMPUTERS += 1 };
+    unsafe { nn_addSupportedArchitecture(computer, (&ARCH_TABLE as *const nn_architecture).cast_mut()) };
 
     let mut gpu_ctrl: nn_gpuControl = nn_gpuControl {
         totalVRAM: 16 * 1024,
@@ -78,16 +213,154 @@ pub extern "C" fn new_computer() -> *mut nn_computer {
         energyPerVRAMChange: 0.0015,
     };
 
-    unsafe { nn_addGPU(computer, null_mut(), 0, &raw mut gpu_ctrl) };
+    let gpu = unsafe { nn_addGPU(computer, null_mut(), 0, &raw mut gpu_ctrl) };
+    let info = DeviceInfo::new(c"display", c"Graphics controller", c"MPG3000 GTZ")
+        .capacity(80 * 25)
+        .width(8);
+    unsafe { set_component_info(computer, gpu, info) };
+
+    let info = DeviceInfo::new(c"system", c"Computer", c"Blocker");
+    unsafe { set_device_info(computer, nn_getComputerAddress(computer), info) };
+    let memory = random_address();
+    let info = DeviceInfo::new(c"memory", c"Memory bank", c"Multipurpose RAM Type 6").capacity(MEMORY_SIZE);
+    unsafe { set_device_info(computer, memory.as_ptr(), info) };
+    unsafe { describe_cpu(computer) };
+
+    unsafe { add_tmpfs(computer) };
+    unsafe { add_default_bios(computer) };
     computer
 }
 
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn describe_cpu(computer: *mut nn_computer) {
+    let machine = unsafe { machine(computer) };
+    let tier = (machine.call_budget * 2.0) as u32;
+    let info = DeviceInfo::new(c"processor", c"CPU", CString::new(format!("FlexiArch {tier} Processor")).unwrap())
+        .width(COMPONENT_LIMIT)
+        .clock((machine.instructions_per_tick as usize).saturating_mul(20));
+    unsafe { set_device_info(computer, machine.cpu_address.as_ptr(), info) };
+}
+
+/// Sets the CPU tier, 1 to 3, which decides how many direct calls the computer makes per tick.
+/// Computers start at tier 3.
+///
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_cpu_tier(computer: *mut nn_computer, tier: i32) -> i32 {
+    assert_ne!(computer, null_mut());
+    unsafe { machine(computer) }.call_budget = match tier {
+        1 => 0.5,
+        2 => 1.0,
+        3 => 1.5,
+        _ => return fail(c"invalid CPU tier"),
+    };
+    unsafe { describe_cpu(computer) };
+    0
+}
+
+/// Sets how many Lua instructions the computer runs per tick before it is paused until the next,
+/// which limits its speed, and how many it may run without yielding before it errors with
+/// "too long without yielding". Computers start at 1000000 and 100000000.
+/// Returns 0 on success or -1 on failure, see last_error.
+///
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_instruction_limits(computer: *mut nn_computer, per_tick: u32, timeout: u64) -> i32 {
+    assert_ne!(computer, null_mut());
+    if per_tick == 0 || timeout == 0 {
+        return fail(c"instruction limits must be positive");
+    }
+    let machine = unsafe { machine(computer) };
+    machine.instructions_per_tick = per_tick as u64;
+    machine.instruction_timeout = timeout;
+    unsafe { describe_cpu(computer) };
+    0
+}
+
+/// Instructions per tick and the timeout, see set_instruction_limits.
+/// # Safety
+/// computer must be valid and created by new_computer
+pub(crate) unsafe fn instruction_limits(computer: *mut nn_computer) -> (u64, u64) {
+    let machine = unsafe { machine(computer) };
+    (machine.instructions_per_tick, machine.instruction_timeout)
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer
+pub(crate) unsafe fn values<'a>(computer: *mut nn_computer) -> &'a mut Values {
+    &mut unsafe { machine(computer) }.values
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer
+pub(crate) unsafe fn devices<'a>(computer: *mut nn_computer) -> &'a mut Devices {
+    &mut unsafe { machine(computer) }.devices
+}
+
+/// # Safety
+/// computer must be valid and created by new_computer
+pub(crate) unsafe fn call_budget(computer: *mut nn_computer) -> f64 {
+    unsafe { machine(computer) }.call_budget
+}
+
+fn make_label(name: &[u8]) -> ([c_char; 128], usize) {
+    let mut label = [0; 128];
+    let len = name.len().min(label.len());
+    for (dst, src) in label.iter_mut().zip(&name[..len]) {
+        *dst = *src as c_char;
+    }
+    (label, len)
+}
+
+/// Mounts an empty volatile filesystem at the computer's tmp address, like OC's /tmp.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn add_tmpfs(computer: *mut nn_computer) {
+    let machine = unsafe { machine(computer) };
+    if machine.tmp_size == 0 {
+        return;
+    }
+
+    let (label, label_len) = make_label(b"tmpfs");
+    let opts = nn_vfilesystemOptions {
+        creationTime: get_time() as usize,
+        maxDirEntries: 64,
+        capacity: machine.tmp_size,
+        isReadOnly: false,
+        label,
+        labelLen: label_len,
+        image: null_mut(),
+        rootEntriesInImage: 0,
+    };
+
+    let mut ctx = get_context();
+    let tmpfs = unsafe { nn_volatileFilesystem(&raw mut ctx, opts, filesystem_control()) };
+    assert_ne!(tmpfs, null_mut());
+    let component = unsafe { nn_addFileSystem(computer, nn_getTmpAddress(computer), -1, tmpfs) };
+    let info = DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(machine.tmp_size);
+    unsafe { set_component_info(computer, component, info) };
+}
+
+/// Drops everything written to /tmp by replacing the tmpfs with a fresh one.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn wipe_tmpfs(computer: *mut nn_computer) {
+    let address = unsafe { nn_getTmpAddress(computer) };
+    if !unsafe { nn_findComponent(computer, address) }.is_null() {
+        unsafe { nn_removeComponent(computer, address) };
+    }
+    unsafe { add_tmpfs(computer) };
+}
+
 /// # Safety
 /// computer must be valid
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn new_screen(computer: *mut nn_computer, add_kb: bool, width: i32, height: i32) -> *mut nn_screen {
     assert_ne!(computer, null_mut());
-    let slot: &mut i32 = unsafe { &mut *(nn_getComputerUserData(computer).cast()) };
+    let slot = &mut unsafe { machine(computer) }.slot;
 
     let mut ctx = get_context();
     let screen = unsafe { nn_newScreen(&raw mut ctx, width, height, 24, 16, 256) };
@@ -97,6 +370,8 @@ pub unsafe extern "C" fn new_screen(computer: *mut nn_computer, add_kb: bool, wi
     if add_kb {
         unsafe { nn_addKeyboard(screen, c"browser keyboard".as_ptr().cast_mut()) };
         unsafe { nn_mountKeyboard(computer, c"browser keyboard".as_ptr().cast_mut(), *slot) };
+        let info = DeviceInfo::new(c"input", c"Keyboard", c"Fancytastic Keyboard Version 3 (FKV-3)");
+        unsafe { set_device_info(computer, c"browser keyboard".as_ptr(), info) };
     }
     for y in 0..height {
         for x in 0..width {
@@ -109,163 +384,866 @@ pub unsafe extern "C" fn new_screen(computer: *mut nn_computer, add_kb: bool, wi
             }) };
         }
     }
-    unsafe { nn_addScreen(computer, null_mut(), *slot, screen) };
+    let component = unsafe { nn_addScreen(computer, null_mut(), *slot, screen) };
+    let info = DeviceInfo::new(c"display", c"Text buffer", c"Text Screen V0")
+        .capacity((width * height) as usize)
+        .width(8);
+    unsafe { set_component_info(computer, component, info) };
 
     *slot += 1;
 
     screen
 }
 
+/// Sizes of the live alloc_block allocations by address, so blocks are always freed with
+/// the layout they were made with and loaders never read past the end of one.
+/// Blocks belong to the host rather than a computer, and are often filled before one exists.
+static mut BLOCKS: BTreeMap<usize, usize> = BTreeMap::new();
+
+fn blocks() -> &'static mut BTreeMap<usize, usize> {
+    unsafe { &mut *&raw mut BLOCKS }
+}
+
+/// Allocates a buffer for passing bytes to the loaders, which only borrow it.
+/// The host releases it with free_block.
+/// Returns null if size is not positive or memory runs out, see last_error.
 #[unsafe(no_mangle)]
 pub extern "C" fn alloc_block(size: i32) -> *mut u8 {
-    assert!(size > 0);
-    unsafe { alloc(Layout::from_size_align(size as usize, 1).unwrap()) }
+    if size <= 0 {
+        fail(c"block size must be positive");
+        return null_mut();
+    }
+    let ptr = unsafe { alloc(Layout::from_size_align(size as usize, 1).unwrap()) };
+    if ptr.is_null() {
+        fail(c"out of memory");
+        return null_mut();
+    }
+    blocks().insert(ptr as usize, size as usize);
+    ptr
+}
+
+/// Returns 0, or -1 if ptr is not a live block from alloc_block, see last_error.
+#[unsafe(no_mangle)]
+pub extern "C" fn free_block(ptr: *mut u8) -> i32 {
+    match blocks().remove(&(ptr as usize)) {
+        Some(size) => {
+            unsafe { dealloc(ptr, Layout::from_size_align(size, 1).unwrap()) };
+            0
+        }
+        None => fail(c"not an allocated block"),
+    }
+}
+
+/// Borrows the first `len` bytes of a live block, checking that they lie inside it.
+fn block<'a>(ptr: *const u8, len: i32) -> Result<&'a [u8], &'static CStr> {
+    let size = *blocks()
+        .get(&(ptr as usize))
+        .ok_or(c"not an allocated block")?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= size)
+        .ok_or(c"length does not fit in the block")?;
+    Ok(unsafe { slice::from_raw_parts(ptr, len) })
+}
+
+/// Like block, but a null pointer stands for no bytes.
+fn optional_block<'a>(ptr: *const u8, len: i32) -> Result<&'a [u8], &'static CStr> {
+    if ptr.is_null() {
+        Ok(&[])
+    } else {
+        block(ptr, len)
+    }
+}
+
+/// Records why a loader failed so the host can fetch it with last_error, and returns -1.
+fn fail(msg: &'static CStr) -> i32 {
+    unsafe { LAST_ERROR = msg };
+    -1
+}
+
+/// Describes why the last loader that returned -1 or null failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn last_error() -> *const c_char {
+    unsafe { LAST_ERROR }.as_ptr()
 }
+
+/// Adds an EEPROM that can hold `code_size` bytes of code and `data_size` bytes of data,
+/// starting with the first `code_len` and `data_len` bytes of the code and data blocks.
+/// The code may be gzip or zstd compressed. The EEPROM is labelled with the `label_len` bytes
+/// at `label`, and if `read_only` is set user code can no longer change its code or label.
+/// If `notify` is not -1, the host's `neoweb_eeprom.changed` import is called with it after every write.
+/// The first EEPROM loaded replaces the built-in BIOS.
+/// Returns a handle for the eeprom_* exports, or null on invalid input, see last_error.
 /// # Safety
-/// code and data must point to code_size and data_size bytes of memory allocated with alloc_block
+/// computer must be valid and created by new_computer
 #[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
 pub unsafe extern "C" fn load_eeprom(
     computer: *mut nn_computer,
-    code: *mut u8,
+    code: *const u8,
     code_size: i32,
     code_len: i32,
-    data: *mut u8,
+    data: *const u8,
     data_size: i32,
     data_len: i32,
-) {
+    label: *const u8,
+    label_len: i32,
+    read_only: bool,
+    notify: i32,
+) -> *mut Eeprom {
     assert_ne!(computer, null_mut());
-    let slot: &mut i32 = unsafe { &mut *(nn_getComputerUserData(computer).cast()) };
-    assert_ne!(code, null_mut());
-
-    let opts = nn_veepromOptions {
-        code: code.cast(),
-        len: code_len as usize,
-        size: code_size as usize,
-        data: data.cast(),
-        dataLen: data_len as usize,
-        dataSize: data_size as usize,
-        label: [0; 128],
-        labelLen: 0,
-        isReadOnly: false,
+    if code.is_null() {
+        fail(c"eeprom code buffer is missing");
+        return null_mut();
+    }
+
+    let result = block(code, code_len)
+        .and_then(|code| Ok((code, optional_block(data, data_len)?, optional_block(label, label_len)?)))
+        .and_then(|(code, data, label)| unsafe {
+            add_eeprom(computer, code, code_size, data, data_size, label, read_only, notify)
+        });
+    match result {
+        Ok(eeprom) => eeprom,
+        Err(msg) => {
+            fail(msg);
+            null_mut()
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn add_eeprom(
+    computer: *mut nn_computer,
+    code: &[u8],
+    code_size: i32,
+    data: &[u8],
+    data_size: i32,
+    label: &[u8],
+    read_only: bool,
+    notify: i32,
+) -> Result<*mut Eeprom, &'static CStr> {
+    let code = decompress(code)?;
+    let code_size = usize::try_from(code_size).map_err(|_| c"invalid eeprom size")?;
+    let data_size = usize::try_from(data_size).map_err(|_| c"invalid eeprom data size")?;
+    if code.len() > code_size {
+        return Err(c"eeprom code is larger than the eeprom");
+    }
+    if data.len() > data_size {
+        return Err(c"eeprom data is larger than the eeprom");
+    }
+    let machine = unsafe { machine(computer) };
+
+    let mut eeprom = Eeprom::new(
+        code.to_vec(),
+        code_size,
+        data.to_vec(),
+        data_size,
+        label.to_vec(),
+        read_only,
+    );
+    eeprom.set_notify(notify);
+
+    // the new EEPROM takes over the BIOS's slot, so the component count stays the same
+    let slot = match machine.bios.take() {
+        Some((address, slot)) => {
+            unsafe { remove_device_info(computer, address.as_ptr()) };
+            unsafe { nn_removeComponent(computer, address.as_ptr().cast_mut()) };
+            slot
+        }
+        None => machine.slot,
     };
+    let eeprom = unsafe { add_eeprom_component(computer, null(), slot, eeprom) };
+    if eeprom.is_null() {
+        return Err(c"too many components");
+    }
+    if slot == machine.slot {
+        machine.slot += 1;
+    }
+    Ok(eeprom)
+}
+
+/// Adds an EEPROM holding the built-in BIOS, which boots /init.lua and offers a boot-device picker.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn add_default_bios(computer: *mut nn_computer) {
+    let machine = unsafe { machine(computer) };
+    let address = random_address();
+    // OC's 4 KiB, unless the license header the BIOS carries pushes it past that
+    let size = DEFAULT_BIOS.len().max(4096);
+    let eeprom = Eeprom::new(DEFAULT_BIOS.to_vec(), size, Vec::new(), 256, b"Lua BIOS".to_vec(), false);
+    if unsafe { add_eeprom_component(computer, address.as_ptr(), machine.slot, eeprom) }.is_null() {
+        unsafe { debug_error(c"could not add the default BIOS".as_ptr()) };
+        return;
+    }
+    machine.bios = Some((address, machine.slot));
+    machine.slot += 1;
+}
+
+/// Points at the EEPROM's current code, eeprom_code_size bytes long, so the host can save it.
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_code(eeprom: *mut Eeprom) -> *const u8 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).code() }.as_ptr()
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_code_size(eeprom: *mut Eeprom) -> i32 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).code() }.len() as i32
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_data(eeprom: *mut Eeprom) -> *const u8 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).data() }.as_ptr()
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_data_size(eeprom: *mut Eeprom) -> i32 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).data() }.len() as i32
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_label(eeprom: *mut Eeprom) -> *const u8 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).label() }.as_ptr()
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_label_size(eeprom: *mut Eeprom) -> i32 {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).label() }.len() as i32
+}
+
+/// # Safety
+/// eeprom must come from load_eeprom and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eeprom_read_only(eeprom: *mut Eeprom) -> bool {
+    assert_ne!(eeprom, null_mut());
+    unsafe { (*eeprom).is_read_only() }
+}
+
+fn filesystem_control() -> nn_filesystemControl {
+    nn_filesystemControl {
+        readBytesPerTick: 65536.0,
+        writeBytesPerTick: 32768.0,
+        removeFilesPerTick: 16.0,
+        createFilesPerTick: 16.0,
+
+        readHeatPerByte: 0.00000015,
+        writeHeatPerByte: 0.0000015,
+        removeHeat: 0.035,
+        createHeat: 0.045,
+
+        readEnergyPerByte: 0.0015,
+        writeEnergyPerByte: 0.0035,
+        removeEnergy: 0.135,
+        createEnergy: 0.325,
+    }
+}
+
+/// Creates a writable filesystem over `root`. The image is never copied, so every entry keeps its
+/// own modification time, and only writes count against `capacity`.
+fn image_filesystem(root: Rc<Node>, label: &[u8], capacity: usize) -> Result<*mut nn_filesystem, &'static CStr> {
+    let overlay = Box::into_raw(Box::new(Overlay::new(root, capacity).label(label)));
 
     let mut ctx = get_context();
+    let fs = unsafe { nn_newFilesystem(&raw mut ctx, overlay_table(overlay), filesystem_control()) };
+    if fs.is_null() {
+        drop(unsafe { Box::from_raw(overlay) });
+        return Err(c"out of memory");
+    }
+    Ok(fs)
+}
 
-    let generic_eeprom = unsafe {
-        nn_volatileEEPROM(
-            &raw mut ctx,
-            opts,
-            nn_eepromControl {
-                readHeatPerByte: 0.0015,
-                writeHeatPerByte: 0.03,
-                readEnergyCostPerByte: 0.001,
-                writeEnergyCostPerByte: 0.05,
-                bytesReadPerTick: 32768.0,
-                bytesWrittenPerTick: 4096.0,
-            },
-        )
+/// Adds `fs` to the computer in the next slot. If there is no room, fs is destroyed, which frees
+/// its table's userdata through deinit, so callers have nothing left to clean up.
+/// # Safety
+/// computer must be valid and created by new_computer, and fs must not be added to any computer yet
+unsafe fn add_filesystem(
+    computer: *mut nn_computer,
+    fs: *mut nn_filesystem,
+    info: DeviceInfo,
+) -> Result<*mut nn_component, &'static CStr> {
+    let slot = &mut unsafe { machine(computer) }.slot;
+    let component = unsafe { nn_addFileSystem(computer, null_mut(), *slot, fs) };
+    if component.is_null() {
+        unsafe { nn_destroyFilesystem(fs) };
+        return Err(c"too many components");
+    }
+    unsafe { set_component_info(computer, component, info) };
+    *slot += 1;
+    Ok(component)
+}
+
+/// Undoes add_filesystem, for loaders that fail after adding some of their filesystems.
+/// Components must be removed newest first, so their slots are handed out again.
+/// # Safety
+/// computer must be valid and created by new_computer, and component must be its newest filesystem
+unsafe fn remove_filesystem(computer: *mut nn_computer, component: *mut nn_component) {
+    let address = unsafe { CStr::from_ptr(nn_getComponentAddress(component)) }.to_owned();
+    unsafe { remove_device_info(computer, address.as_ptr()) };
+    unsafe { nn_removeComponent(computer, address.as_ptr().cast_mut()) };
+    unsafe { machine(computer) }.slot -= 1;
+}
+
+/// Adds a writable filesystem over `root` to the computer.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn mount_image(computer: *mut nn_computer, root: Node, label: &[u8]) -> Result<*mut nn_component, &'static CStr> {
+    let vfs = image_filesystem(Rc::new(root), label, 1024 * 1024)?;
+    let info = DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(1024 * 1024);
+    unsafe { add_filesystem(computer, vfs, info) }
+}
+
+/// Reads the image from the first `size` bytes of a block from alloc_block.
+/// Accepts any format Node::from_bytes does, including gzip and zstd compressed images.
+/// Returns 0 on success or -1 if the image is malformed, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn load_vfs(computer: *mut nn_computer, ptr: *const u8, size: i32) -> i32 {
+    assert_ne!(computer, null_mut());
+    if ptr.is_null() || size <= 0 {
+        return fail(c"filesystem image buffer is missing");
+    }
+
+    let result = block(ptr, size)
+        .and_then(Node::from_bytes)
+        .and_then(|root| unsafe { mount_image(computer, root, b"") });
+    match result {
+        Ok(_) => 0,
+        Err(msg) => fail(msg),
+    }
+}
+
+/// Mounts one section of a neotar file, labelled with the `label_len` bytes at `label`.
+/// Both are blocks from alloc_block, and label may be null.
+/// Returns 0 on success or -1 on failure, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn load_vfs_section(
+    computer: *mut nn_computer,
+    ptr: *const u8,
+    size: i32,
+    section: i32,
+    label: *const u8,
+    label_len: i32,
+) -> i32 {
+    assert_ne!(computer, null_mut());
+    if ptr.is_null() || size <= 0 {
+        return fail(c"filesystem image buffer is missing");
+    }
+
+    let result = optional_block(label, label_len).and_then(|label| {
+        let sections = Node::sections_from_bytes(block(ptr, size)?)?;
+        let root = usize::try_from(section)
+            .ok()
+            .and_then(|section| sections.into_iter().nth(section))
+            .ok_or(c"no such section")?;
+        unsafe { mount_image(computer, root, label) }
+    });
+    match result {
+        Ok(_) => 0,
+        Err(msg) => fail(msg),
+    }
+}
+
+/// Mounts every section of a neotar file in a block from alloc_block as its own filesystem,
+/// labelled `disk0`, `disk1`, ...
+/// Returns the number of filesystems added, or -1 on failure, see last_error. On failure none are added.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn load_vfs_sections(computer: *mut nn_computer, ptr: *const u8, size: i32) -> i32 {
+    assert_ne!(computer, null_mut());
+    if ptr.is_null() || size <= 0 {
+        return fail(c"filesystem image buffer is missing");
+    }
+
+    let result = block(ptr, size).and_then(Node::sections_from_bytes).and_then(|sections| {
+        let count = sections.len() as i32;
+        let mut mounted = Vec::with_capacity(sections.len());
+        for (i, root) in sections.into_iter().enumerate() {
+            let label = format!("disk{i}");
+            match unsafe { mount_image(computer, root, label.as_bytes()) } {
+                Ok(component) => mounted.push(component),
+                Err(msg) => {
+                    for &component in mounted.iter().rev() {
+                        unsafe { remove_filesystem(computer, component) };
+                    }
+                    return Err(msg);
+                }
+            }
+        }
+        Ok(count)
+    });
+    result.unwrap_or_else(fail)
+}
+
+/// Loads a filesystem image from a block from alloc_block once, so it can back any number of overlays.
+/// Returns null if the image is malformed, see last_error.
+#[unsafe(no_mangle)]
+pub extern "C" fn load_base_image(ptr: *const u8, size: i32) -> *mut Rc<Node> {
+    if ptr.is_null() || size <= 0 {
+        fail(c"filesystem image buffer is missing");
+        return null_mut();
+    }
+    match block(ptr, size).and_then(Node::from_bytes) {
+        Ok(root) => Box::into_raw(Box::new(Rc::new(root))),
+        Err(msg) => {
+            fail(msg);
+            null_mut()
+        }
+    }
+}
+
+/// Releases the host's reference to a base image. Mounted overlays keep it alive.
+/// # Safety
+/// base must come from load_base_image and not be used afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_base_image(base: *mut Rc<Node>) {
+    assert_ne!(base, null_mut());
+    drop(unsafe { Box::from_raw(base) });
+}
+
+/// Adds a writable copy-on-write filesystem over `base` to the computer.
+/// Returns null if it could not be added, see last_error.
+/// # Safety
+/// computer and base must be valid
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mount_overlay(computer: *mut nn_computer, base: *mut Rc<Node>) -> *mut Overlay {
+    assert_ne!(computer, null_mut());
+    assert_ne!(base, null_mut());
+
+    let overlay = Box::into_raw(Box::new(Overlay::new(unsafe { (*base).clone() }, 1024 * 1024)));
+
+    let mut ctx = get_context();
+    let fs = unsafe { nn_newFilesystem(&raw mut ctx, overlay_table(overlay), filesystem_control()) };
+    if fs.is_null() {
+        drop(unsafe { Box::from_raw(overlay) });
+        fail(c"out of memory");
+        return null_mut();
+    }
+    let info = DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(1024 * 1024);
+    if let Err(msg) = unsafe { add_filesystem(computer, fs, info) } {
+        fail(msg);
+        return null_mut();
+    }
+    overlay
+}
+
+/// Adds a filesystem whose storage is provided by the host through the `neoweb_fs` imports,
+/// which receive `disk` with every call.
+/// Fails while the computer is recording or replaying, see start_recording.
+/// Returns 0 on success or -1 on failure, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn load_host_fs(computer: *mut nn_computer, disk: i32, capacity: i32, read_only: bool) -> i32 {
+    assert_ne!(computer, null_mut());
+    if capacity < 0 {
+        return fail(c"invalid capacity");
+    }
+    let machine = unsafe { machine(computer) };
+    if machine.recording.is_some() || machine.replay.is_some() {
+        return fail(c"host filesystems can't be recorded");
+    }
+    let host = Box::into_raw(Box::new(HostFs::new(disk, capacity as usize, read_only)));
+
+    let mut ctx = get_context();
+    let fs = unsafe { nn_newFilesystem(&raw mut ctx, host_table(host), filesystem_control()) };
+    if fs.is_null() {
+        drop(unsafe { Box::from_raw(host) });
+        return fail(c"out of memory");
+    }
+    let info = DeviceInfo::new(c"volume", c"Filesystem", FILESYSTEM).capacity(capacity as usize);
+    match unsafe { add_filesystem(computer, fs, info) } {
+        Ok(_) => {
+            machine.host_disks += 1;
+            0
+        }
+        Err(msg) => fail(msg),
+    }
+}
+
+/// Adds an unmanaged drive of `capacity` bytes starting with the first `size` bytes of a block
+/// from alloc_block. ptr may be null for a blank drive. `speed` scales how many sectors can be accessed per tick.
+/// Returns a handle for drive_data, or null on failure, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn load_drive(
+    computer: *mut nn_computer,
+    ptr: *const u8,
+    size: i32,
+    capacity: i32,
+    sector_size: i32,
+    platters: i32,
+    speed: f64,
+) -> *mut Drive {
+    assert_ne!(computer, null_mut());
+    let image = match optional_block(ptr, size) {
+        Ok(image) => image,
+        Err(msg) => {
+            fail(msg);
+            return null_mut();
+        }
     };
+    if sector_size <= 0 || platters <= 0 || capacity <= 0 || capacity % sector_size != 0 {
+        fail(c"capacity must be a positive multiple of the sector size");
+        return null_mut();
+    }
+    if image.len() > capacity as usize {
+        fail(c"drive image is larger than its capacity");
+        return null_mut();
+    }
+    if speed.is_nan() || speed <= 0.0 {
+        fail(c"drive speed must be positive");
+        return null_mut();
+    }
+    let slot = &mut unsafe { machine(computer) }.slot;
 
-    unsafe { nn_addEEPROM(computer, null_mut(), *slot, generic_eeprom) };
+    let drive = Drive::new(image, capacity as usize, sector_size as usize, platters as usize, speed);
+    let drive = unsafe { add_drive_component(computer, *slot, drive) };
+    if drive.is_null() {
+        fail(c"too many components");
+        return null_mut();
+    }
     *slot += 1;
+    drive
+}
 
-    unsafe {
-        dealloc(
-            code,
-            Layout::from_size_align(code_size as usize, 1).unwrap(),
-        )
+/// Points at the current contents of a drive, drive_size bytes long, so the host can save it.
+/// # Safety
+/// drive must come from load_drive and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn drive_data(drive: *mut Drive) -> *const u8 {
+    assert_ne!(drive, null_mut());
+    unsafe { (*drive).data() }.as_ptr()
+}
+
+/// # Safety
+/// drive must come from load_drive and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn drive_size(drive: *mut Drive) -> i32 {
+    assert_ne!(drive, null_mut());
+    unsafe { (*drive).data() }.len() as i32
+}
+
+/// Adds a probe, only in builds with the `probe` feature, a component whose `value` and `writes` fields check component.fields and
+/// proxy field access from Lua, and whose `counter` method returns a value object.
+/// Returns 0 on success or -1 if it could not be added, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[cfg(feature = "probe")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn add_probe(computer: *mut nn_computer) -> i32 {
+    assert_ne!(computer, null_mut());
+    let slot = &mut unsafe { machine(computer) }.slot;
+    if !unsafe { add_probe_component(computer, *slot) } {
+        return fail(c"too many components");
+    }
+    *slot += 1;
+    0
+}
+
+/// Adds an empty disk drive that floppies can be inserted into with insert_floppy.
+/// Returns null if it could not be added, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn new_disk_drive(computer: *mut nn_computer) -> *mut DiskDrive {
+    assert_ne!(computer, null_mut());
+    let slot = &mut unsafe { machine(computer) }.slot;
+
+    let drive = unsafe { add_disk_drive(computer, *slot) };
+    if drive.is_null() {
+        fail(c"too many components");
+        return null_mut();
+    }
+    *slot += 1;
+    unsafe { machine(computer) }.disk_drives.push(drive);
+    drive
+}
+
+/// # Safety
+/// drive must come from new_disk_drive and its computer must still exist
+unsafe fn insert_image(drive: *mut DiskDrive, image: &[u8]) -> Result<(), &'static CStr> {
+    let root = Node::from_bytes(image)?;
+    let fs = image_filesystem(Rc::new(root), b"floppy", 512 * 1024)?;
+    unsafe { (*drive).insert(fs) }
+}
+
+/// The drive's index in its computer's disk_drives, as recordings store it.
+/// # Safety
+/// drive must come from new_disk_drive and its computer must still exist
+unsafe fn drive_index(drive: *mut DiskDrive) -> Result<usize, &'static CStr> {
+    let machine = unsafe { machine((*drive).computer()) };
+    machine
+        .disk_drives
+        .iter()
+        .position(|&d| d == drive)
+        .ok_or(c"the disk drive is not in its computer")
+}
+
+/// Inserts a floppy holding a copy of the image in a block from alloc_block, ejecting the previous one.
+/// Accepts any format load_vfs does. Returns 0 on success or -1 on failure, see last_error.
+/// # Safety
+/// drive must come from new_disk_drive and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn insert_floppy(drive: *mut DiskDrive, ptr: *const u8, size: i32) -> i32 {
+    assert_ne!(drive, null_mut());
+    if ptr.is_null() || size <= 0 {
+        return fail(c"floppy image buffer is missing");
+    }
+
+    let computer = unsafe { (*drive).computer() };
+    if unsafe { machine(computer) }.replay.is_some() {
+        return fail(c"a replay is running");
+    }
+
+    let result = block(ptr, size).and_then(|image| {
+        let index = unsafe { drive_index(drive) }?;
+        unsafe { insert_image(drive, image) }?;
+        let image = image.to_vec();
+        unsafe { record(computer, Input::InsertFloppy { drive: index, image }) };
+        Ok(())
+    });
+    match result {
+        Ok(()) => 0,
+        Err(msg) => fail(msg),
+    }
+}
+
+/// Returns 1 if a floppy was ejected, 0 if the drive was already empty or a replay is running,
+/// or -1 on failure, see last_error.
+/// # Safety
+/// drive must come from new_disk_drive and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn eject_floppy(drive: *mut DiskDrive) -> i32 {
+    assert_ne!(drive, null_mut());
+    let computer = unsafe { (*drive).computer() };
+    let index = match unsafe { drive_index(drive) } {
+        Ok(index) => index,
+        Err(msg) => return fail(msg),
     };
-    if !data.is_null() && data_size > 0 {
-        unsafe {
-            dealloc(
-                data,
-                Layout::from_size_align(data_size as usize, 1).unwrap(),
-            )
-        };
+    if unsafe { machine(computer) }.replay.is_some() || !unsafe { (*drive).eject() } {
+        return 0;
     }
+    unsafe { record(computer, Input::EjectFloppy { drive: index }) };
+    1
+}
+
+/// Writes the overlay's current contents out as a ustar archive with modification times.
+/// Returns a handle for export_data and export_size, to be released with free_export.
+/// # Safety
+/// overlay must come from mount_overlay and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn export_overlay(overlay: *mut Overlay) -> *mut Vec<u8> {
+    assert_ne!(overlay, null_mut());
+    Box::into_raw(Box::new(unsafe { (*overlay).export() }))
 }
 
 /// # Safety
-/// ptr must point to size bytes of memory allocated with alloc_block
+/// export must come from export_overlay or stop_recording
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn load_vfs(computer: *mut nn_computer, ptr: *mut u8, size: i32) {
+pub unsafe extern "C" fn export_data(export: *mut Vec<u8>) -> *const u8 {
+    assert_ne!(export, null_mut());
+    unsafe { (*export).as_ptr() }
+}
+
+/// # Safety
+/// export must come from export_overlay or stop_recording
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn export_size(export: *mut Vec<u8>) -> i32 {
+    assert_ne!(export, null_mut());
+    unsafe { (*export).len() as i32 }
+}
+
+/// # Safety
+/// export must come from export_overlay or stop_recording and not be used afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_export(export: *mut Vec<u8>) {
+    assert_ne!(export, null_mut());
+    drop(unsafe { Box::from_raw(export) });
+}
+
+/// Throws away everything written to an overlay, restoring the base image.
+/// # Safety
+/// overlay must come from mount_overlay and its computer must still exist
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn reset_overlay(overlay: *mut Overlay) {
+    assert_ne!(overlay, null_mut());
+    unsafe { (*overlay).reset() };
+}
+
+/// Starts logging the computer's key presses and floppy insertions and ejections, for start_replay.
+/// Only works in deterministic mode, as replays depend on the same clock and RNG.
+/// Host filesystems answer from storage outside the computer, so computers with one can't be
+/// recorded. EEPROM change notifications only go out to the host and need no recording.
+/// Signals pushed by the host any other way, and calls into host components, are not recorded.
+/// The computer must not have ticked yet, so the recording starts from the state
+/// enable_deterministic gives, which its header stores.
+/// Returns 0 on success or -1 on failure, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn start_recording(computer: *mut nn_computer) -> i32 {
     assert_ne!(computer, null_mut());
-    let slot: &mut i32 = unsafe { &mut *(nn_getComputerUserData(computer).cast()) };
-
-    let bytes = unsafe { slice::from_raw_parts(ptr, size as usize) };
-    let file = neotar::File::read(bytes).0;
-    file.sanity_check();
-    let section = file.sections[0];
-    let (entries, root_len) = neotar::files::read_entries_recursive(&section);
-
-    let mut image = Vec::with_capacity(entries.len());
-
-    for entry in entries.iter() {
-        let name = entry.name.as_ptr();
-        match entry.entry {
-            neotar::files::EntryInner::File(contents) => {
-                image.push(nn_vfilesystemImageNode {
-                    name,
-                    data: contents.as_ptr().cast(),
-                    len: contents.len(),
-                });
-            }
-            neotar::files::EntryInner::Directory(len) => {
-                image.push(nn_vfilesystemImageNode {
-                    name,
-                    data: null_mut(),
-                    len: len as usize,
-                });
-            }
+    let Some(settings) = deterministic() else {
+        return fail(c"recording needs deterministic mode");
+    };
+    let machine = unsafe { machine(computer) };
+    if machine.host_disks > 0 {
+        return fail(c"host filesystems can't be recorded");
+    }
+    if machine.ticks > 0 {
+        return fail(c"recording must start before the computer first ticks");
+    }
+    machine.recording = Some(Recording::new(settings));
+    0
+}
+
+/// Stops recording and returns the log as a handle for export_data and export_size,
+/// to be released with free_export. Returns null if the computer was not recording, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stop_recording(computer: *mut nn_computer) -> *mut Vec<u8> {
+    assert_ne!(computer, null_mut());
+    match unsafe { machine(computer) }.recording.take() {
+        Some(recording) => Box::into_raw(Box::new(recording.finish())),
+        None => {
+            fail(c"the computer is not recording");
+            null_mut()
         }
     }
+}
 
-    let opts = nn_vfilesystemOptions {
-        creationTime: 0,
-        maxDirEntries: 64,
-        capacity: 1024 * 1024,
-        isReadOnly: false,
-        label: [0; 128],
-        labelLen: 0,
-        image: image.as_mut_ptr(),
-        rootEntriesInImage: root_len as usize,
+/// Feeds a log from stop_recording, in a block from alloc_block, to the computer. It must be set
+/// up like the recorded one was, under the same enable_deterministic settings, and must not have
+/// ticked yet. Input from the host is ignored until the replay is over.
+/// Returns 0 on success or -1 on failure, see last_error.
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn start_replay(computer: *mut nn_computer, ptr: *const u8, size: i32) -> i32 {
+    assert_ne!(computer, null_mut());
+    let Some(settings) = deterministic() else {
+        return fail(c"replaying needs deterministic mode");
     };
+    if ptr.is_null() || size <= 0 {
+        return fail(c"replay buffer is missing");
+    }
+    let machine = unsafe { machine(computer) };
+    if machine.host_disks > 0 {
+        return fail(c"host filesystems can't be recorded");
+    }
+    if machine.ticks > 0 {
+        return fail(c"replaying must start before the computer first ticks");
+    }
+    match block(ptr, size).and_then(|log| Replay::parse(log, settings)) {
+        Ok(replay) => {
+            machine.replay = Some(replay);
+            0
+        }
+        Err(msg) => fail(msg),
+    }
+}
 
-    let mut ctx = get_context();
+/// # Safety
+/// computer must be valid and created by new_computer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn is_replaying(computer: *mut nn_computer) -> bool {
+    assert_ne!(computer, null_mut());
+    unsafe { machine(computer) }.replay.is_some()
+}
 
-    let vfs = unsafe {
-        nn_volatileFilesystem(
-            &raw mut ctx,
-            opts,
-            nn_filesystemControl {
-                readBytesPerTick: 65536.0,
-                writeBytesPerTick: 32768.0,
-                removeFilesPerTick: 16.0,
-                createFilesPerTick: 16.0,
-
-                readHeatPerByte: 0.00000015,
-                writeHeatPerByte: 0.0000015,
-                removeHeat: 0.035,
-                createHeat: 0.045,
-
-                readEnergyPerByte: 0.0015,
-                writeEnergyPerByte: 0.0035,
-                removeEnergy: 0.135,
-                createEnergy: 0.325,
-            },
-        )
+/// Adds the input to the computer's recording, if it is recording.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn record(computer: *mut nn_computer, input: Input) {
+    let machine = unsafe { machine(computer) };
+    if let Some(recording) = &mut machine.recording {
+        recording.record(machine.ticks, &input);
+    }
+}
+
+/// Applies the replay's inputs that are due before this tick, and ends it once all are applied.
+/// # Safety
+/// computer must be valid and created by new_computer
+unsafe fn replay_inputs(computer: *mut nn_computer) {
+    let machine = unsafe { machine(computer) };
+    let Some(replay) = &mut machine.replay else {
+        return;
     };
-    unsafe { nn_addFileSystem(computer, null_mut(), *slot, vfs) };
-    *slot += 1;
-    unsafe { dealloc(ptr, Layout::from_size_align(size as usize, 1).unwrap()) };
+    let mut due = Vec::new();
+    while let Some(input) = replay.next_due(machine.ticks) {
+        due.push(input);
+    }
+    if replay.is_finished() {
+        machine.replay = None;
+    }
+
+    for input in due {
+        match input {
+            Input::Key { char, code, released } => unsafe {
+                push_key(computer, char as i32, code as i32, released)
+            },
+            Input::InsertFloppy { drive, image } => {
+                let Some(&drive) = machine.disk_drives.get(drive) else {
+                    unsafe { debug_error(c"replay refers to a missing disk drive".as_ptr()) };
+                    continue;
+                };
+                if let Err(msg) = unsafe { insert_image(drive, &image) } {
+                    unsafe { debug_error(msg.as_ptr()) };
+                }
+            }
+            Input::EjectFloppy { drive } => {
+                let Some(&drive) = machine.disk_drives.get(drive) else {
+                    unsafe { debug_error(c"replay refers to a missing disk drive".as_ptr()) };
+                    continue;
+                };
+                unsafe { (*drive).eject() };
+            }
+        }
+    }
 }
 
+/// Ignored while a replay is running.
 /// # Safety
 /// Perhaps
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn on_key(computer: *mut nn_computer, char: i32, code: i32, released: bool) {
     assert_ne!(computer, null_mut());
+    if unsafe { machine(computer) }.replay.is_some() {
+        return;
+    }
+    let input = Input::Key {
+        char: char as u32,
+        code: code as u32,
+        released,
+    };
+    unsafe { record(computer, input) };
+    unsafe { push_key(computer, char, code, released) };
+}
 
+unsafe fn push_key(computer: *mut nn_computer, char: i32, code: i32, released: bool) {
     unsafe {
         let mut values: [nn_value; 5] = [
             nn_values_cstring(if released {
@@ -282,11 +1260,23 @@ pub unsafe extern "C" fn on_key(computer: *mut nn_computer, char: i32, code: i32
     }
 }
 
+/// Returns false once the machine has stopped on an error, such as an uncaught Lua error or
+/// running too long without yielding. Only that computer stops, and ticking it again boots it
+/// afresh, like pressing its power button.
 /// # Safety
 /// computer must be valid
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn tick(computer: *mut nn_computer) {
+pub unsafe extern "C" fn tick(computer: *mut nn_computer) -> bool {
     assert_ne!(computer, null_mut());
+    if unsafe { machine(computer) }.halted {
+        unsafe { debug_log(c"boot".as_ptr()) };
+        let arch = (&ARCH_TABLE as *const nn_architecture).cast_mut();
+        unsafe { nn_swapArchitecture(computer, arch) };
+        unsafe { machine(computer) }.halted = false;
+    }
+    advance_clock();
+    unsafe { replay_inputs(computer) };
+    unsafe { machine(computer) }.ticks += 1;
 
     unsafe { nn_setEnergyInfo(computer, f64::INFINITY, f64::INFINITY) };
         
@@ -295,31 +1285,46 @@ pub unsafe extern "C" fn tick(computer: *mut nn_computer) {
     
     if unsafe { nn_isOverheating(computer) } {
         unsafe { debug_error(c"overheating".as_ptr()) };
-        return;
+        return true;
     }
 
     let state = unsafe { nn_tickComputer(computer) };
     #[allow(non_snake_case)] // this lint is just wrong here??
     match state {
         NN_STATE_SWITCH => {
-            unsafe { debug_log(c"state switch".as_ptr()) };
+            // tears down the running architecture and sets up the one setArchitecture picked,
+            // which is a reboot as far as the machine can tell
+            unsafe { debug_log(c"architecture switch".as_ptr()) };
+            let next = unsafe { nn_getNextArchitecture(computer) };
+            if !next.is_null() {
+                unsafe { nn_swapArchitecture(computer, next) };
+            }
+            unsafe { wipe_tmpfs(computer) };
         }
         NN_STATE_BLACKOUT => {
             unsafe { debug_log(c"blackout".as_ptr()) };
         }
         NN_STATE_REPEAT => {
             unsafe { debug_log(c"reboot".as_ptr()) };
+            unsafe { wipe_tmpfs(computer) };
         }
         NN_STATE_CLOSING => {
+            // if the machine is started again, that boot must not see this run's /tmp either
             unsafe { debug_log(c"shutdown".as_ptr()) };
+            unsafe { wipe_tmpfs(computer) };
         }
         _ => {}
     }
     let error = unsafe { nn_getError(computer) };
     if !error.is_null() {
-        unsafe { debug_error(error.cast()) }
-        unreachable!();
+        // the Lua state is dead, so it is set up again on the next tick
+        unsafe { debug_error(error.cast()) };
+        unsafe { nn_clearError(computer) };
+        unsafe { wipe_tmpfs(computer) };
+        unsafe { machine(computer) }.halted = true;
+        return false;
     }
+    true
 }
 
 /// # Safety
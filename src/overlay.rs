@@ -0,0 +1,837 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{CStr, c_char, c_int, c_void},
+    iter,
+    ptr::null_mut,
+    rc::Rc,
+};
+
+use neonucleus::ffi::{nn_Alloc, nn_errorbuf_t, nn_filesystemTable};
+
+use crate::archive::{TarEntry, write_tar};
+use crate::context::get_time;
+use crate::filesystem::{get_label, label_arg, name_list, path_arg, set_error};
+use crate::image::Node;
+
+/// Times are in seconds since the unix epoch, as in [`Node`].
+enum Change {
+    File { data: Vec<u8>, modified: u64 },
+    /// An opaque directory hides whatever the base image has at the same path.
+    Directory { opaque: bool, modified: u64 },
+    Removed,
+}
+
+impl Change {
+    fn size(&self) -> usize {
+        match self {
+            Change::File { data, .. } => data.len(),
+            _ => 0,
+        }
+    }
+}
+
+enum Found<'a> {
+    File(&'a [u8]),
+    Directory,
+}
+
+struct Handle {
+    path: String,
+    position: usize,
+    writable: bool,
+}
+
+/// Writable layer over a shared read-only image. Only changed paths are stored.
+pub struct Overlay {
+    base: Rc<Node>,
+    changes: BTreeMap<String, Change>,
+    /// Indexed by file descriptor minus one, so the 0 fs_open returns on failure is never open.
+    handles: Vec<Option<Handle>>,
+    label: Vec<u8>,
+    capacity: usize,
+    /// Bytes of file data in `changes`. Only these count against the capacity, as the base image
+    /// is shared and never written to. Moving a file out of the base copies it into the overlay,
+    /// so it counts from then on.
+    used: usize,
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{parent}/{name}")
+    }
+}
+
+impl Overlay {
+    pub fn new(base: Rc<Node>, capacity: usize) -> Self {
+        Self {
+            base,
+            changes: BTreeMap::new(),
+            handles: Vec::new(),
+            label: Vec::new(),
+            capacity,
+            used: 0,
+        }
+    }
+
+    pub fn label(mut self, label: &[u8]) -> Self {
+        self.label = label.to_vec();
+        self
+    }
+
+    /// Drops every change and open handle, leaving only the base image.
+    pub fn reset(&mut self) {
+        self.changes.clear();
+        self.handles.clear();
+        self.used = 0;
+    }
+
+    /// Records a change at `path`, keeping `used` in step with the one it replaces.
+    fn change(&mut self, path: &str, change: Change) {
+        self.used += change.size();
+        if let Some(old) = self.changes.insert(path.to_owned(), change) {
+            self.used -= old.size();
+        }
+    }
+
+    fn handle(&self, fd: usize) -> Option<&Handle> {
+        self.handles.get(fd.checked_sub(1)?)?.as_ref()
+    }
+
+    /// Returns what is visible at `path` and the base node it came from, if any.
+    fn resolve(&self, path: &str) -> Option<(Found<'_>, Option<&Node>)> {
+        let mut base = Some(&*self.base);
+        let mut current = Found::Directory;
+        if path.is_empty() {
+            return Some((current, base));
+        }
+        let prefixes = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .chain(iter::once(path));
+        for (prefix, name) in prefixes.zip(path.split('/')) {
+            let Found::Directory = current else {
+                return None;
+            };
+            base = base.and_then(|node| node.child(name));
+            current = match self.changes.get(prefix) {
+                Some(Change::Removed) => return None,
+                Some(Change::File { data, .. }) => {
+                    base = None;
+                    Found::File(data)
+                }
+                Some(Change::Directory { opaque, .. }) => {
+                    if *opaque {
+                        base = None;
+                    }
+                    Found::Directory
+                }
+                None => match base? {
+                    Node::File { data, .. } => Found::File(data),
+                    Node::Directory { .. } => Found::Directory,
+                },
+            };
+        }
+        Some((current, base))
+    }
+
+    /// The base image's node at `path`, even if changes hide it.
+    fn base_node(&self, path: &str) -> Option<&Node> {
+        if path.is_empty() {
+            return Some(&self.base);
+        }
+        path.split('/')
+            .try_fold(&*self.base, |node, name| node.child(name))
+    }
+
+    /// Bytes of file data visible under `path`.
+    fn tree_size(&self, path: &str) -> usize {
+        match self.resolve(path) {
+            Some((Found::File(data), _)) => data.len(),
+            Some((Found::Directory, _)) => self
+                .list(path)
+                .unwrap_or_default()
+                .iter()
+                .map(|name| self.tree_size(&child_path(path, name.trim_end_matches('/'))))
+                .sum(),
+            None => 0,
+        }
+    }
+
+    /// Bytes of file data in the changes at and under `path`, which removing it frees.
+    fn changed_size(&self, path: &str) -> usize {
+        let prefix = child_path(path, "");
+        self.changes
+            .iter()
+            .filter(|(key, _)| *key == path || key.starts_with(prefix.as_str()))
+            .map(|(_, change)| change.size())
+            .sum()
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.resolve(path).is_some()
+    }
+
+    fn is_directory(&self, path: &str) -> bool {
+        matches!(self.resolve(path), Some((Found::Directory, _)))
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        match self.resolve(path)? {
+            (Found::File(data), _) => Some(data),
+            (Found::Directory, _) => None,
+        }
+    }
+
+    fn modified(&self, path: &str) -> Option<u64> {
+        let (_, base) = self.resolve(path)?;
+        match self.changes.get(path) {
+            Some(Change::File { modified, .. } | Change::Directory { modified, .. }) => Some(*modified),
+            _ => base.map(Node::modified),
+        }
+    }
+
+    /// Lists a directory, with subdirectories suffixed by `/` as OC does.
+    fn list(&self, path: &str) -> Option<Vec<String>> {
+        let (Found::Directory, base) = self.resolve(path)? else {
+            return None;
+        };
+        let mut names: Vec<String> = Vec::new();
+        if let Some(Node::Directory { children, .. }) = base {
+            names.extend(children.keys().cloned());
+        }
+        let prefix = child_path(path, "");
+        for key in self.changes.keys() {
+            if let Some(name) = key.strip_prefix(prefix.as_str())
+                && !name.is_empty()
+                && !name.contains('/')
+            {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        names.dedup();
+
+        let mut list = Vec::with_capacity(names.len());
+        for name in names {
+            match self.resolve(&child_path(path, &name)) {
+                Some((Found::Directory, _)) => list.push(format!("{name}/")),
+                Some((Found::File(_), _)) => list.push(name),
+                None => {}
+            }
+        }
+        Some(list)
+    }
+
+    fn remove(&mut self, path: &str) -> bool {
+        if path.is_empty() {
+            return false;
+        }
+        if !self.exists(path) {
+            return false;
+        }
+        // changes may hide the base entry, but it comes back unless marked as removed
+        let shadows_base = self.base_node(path).is_some();
+        let prefix = child_path(path, "");
+        let mut freed = 0;
+        self.changes.retain(|key, change| {
+            let keep = key != path && !key.starts_with(prefix.as_str());
+            if !keep {
+                freed += change.size();
+            }
+            keep
+        });
+        self.used -= freed;
+        if shadows_base {
+            self.changes.insert(path.to_owned(), Change::Removed);
+        }
+        true
+    }
+
+    fn make_directory(&mut self, path: &str) -> Result<bool, &'static CStr> {
+        if self.exists(path) {
+            return Ok(false);
+        }
+        let prefixes = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .chain(iter::once(path));
+        for prefix in prefixes {
+            match self.resolve(prefix) {
+                Some((Found::Directory, _)) => {}
+                Some((Found::File(_), _)) => return Err(c"file already exists"),
+                None => {
+                    let opaque = matches!(self.changes.get(prefix), Some(Change::Removed));
+                    self.change(
+                        prefix,
+                        Change::Directory {
+                            opaque,
+                            modified: get_time() as u64,
+                        },
+                    );
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Copies everything visible under `from` to `to` as changes, keeping modification times.
+    fn copy_tree(&mut self, from: &str, to: &str) {
+        let modified = self.modified(from).unwrap_or_default();
+        match self.resolve(from) {
+            Some((Found::File(data), _)) => {
+                let data = data.to_vec();
+                self.change(to, Change::File { data, modified });
+            }
+            Some((Found::Directory, _)) => {
+                self.change(
+                    to,
+                    Change::Directory {
+                        opaque: true,
+                        modified,
+                    },
+                );
+                for name in self.list(from).unwrap_or_default() {
+                    let name = name.trim_end_matches('/');
+                    self.copy_tree(&child_path(from, name), &child_path(to, name));
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), &'static CStr> {
+        if from.is_empty() || !self.exists(from) {
+            return Err(c"no such file or directory");
+        }
+        if self.exists(to) {
+            return Err(c"file already exists");
+        }
+        if to.starts_with(&child_path(from, "")) {
+            return Err(c"cannot move a directory into itself");
+        }
+        let parent = to.rsplit_once('/').map_or("", |(parent, _)| parent);
+        if !self.is_directory(parent) {
+            return Err(c"no such file or directory");
+        }
+        // whatever comes from the base image is copied into the overlay and counts from then on
+        if self.used - self.changed_size(from) + self.tree_size(from) > self.capacity {
+            return Err(c"not enough space");
+        }
+        self.copy_tree(from, to);
+        self.remove(from);
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, mode: &str) -> Result<usize, &'static CStr> {
+        let writable = mode.contains('w') || mode.contains('a');
+        let position = match (self.resolve(path), writable) {
+            (Some((Found::Directory, _)), _) => return Err(c"is a directory"),
+            (None, false) => return Err(c"no such file"),
+            (Some((Found::File(_), _)), false) => 0,
+            (existing, true) => {
+                let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                if path.is_empty() || !self.is_directory(parent) {
+                    return Err(c"no such file or directory");
+                }
+                let data = match existing {
+                    Some((Found::File(data), _)) if mode.contains('a') => data.to_vec(),
+                    _ => Vec::new(),
+                };
+                let len = data.len();
+                let replaced = self.changes.get(path).map_or(0, Change::size);
+                if self.used - replaced + len > self.capacity {
+                    return Err(c"not enough space");
+                }
+                self.change(
+                    path,
+                    Change::File {
+                        data,
+                        modified: get_time() as u64,
+                    },
+                );
+                len
+            }
+        };
+        let handle = Handle {
+            path: path.to_owned(),
+            position,
+            writable,
+        };
+        let index = match self.handles.iter().position(Option::is_none) {
+            Some(index) => {
+                self.handles[index] = Some(handle);
+                index
+            }
+            None => {
+                self.handles.push(Some(handle));
+                self.handles.len() - 1
+            }
+        };
+        Ok(index + 1)
+    }
+
+    fn close(&mut self, fd: usize) -> bool {
+        let Some(index) = fd.checked_sub(1) else {
+            return false;
+        };
+        match self.handles.get_mut(index) {
+            Some(handle @ Some(_)) => {
+                *handle = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read(&mut self, fd: usize, buf: &mut [u8]) -> Result<usize, &'static CStr> {
+        let Some(handle) = self.handle(fd) else {
+            return Err(c"bad file descriptor");
+        };
+        let position = handle.position;
+        let Some(data) = self.file(&handle.path) else {
+            return Err(c"no such file");
+        };
+        let start = position.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        if let Some(Some(handle)) = self.handles.get_mut(fd - 1) {
+            handle.position = start + len;
+        }
+        Ok(len)
+    }
+
+    fn write(&mut self, fd: usize, buf: &[u8]) -> Result<(), &'static CStr> {
+        let handle = fd
+            .checked_sub(1)
+            .and_then(|index| self.handles.get_mut(index));
+        let Some(Some(handle)) = handle else {
+            return Err(c"bad file descriptor");
+        };
+        if !handle.writable {
+            return Err(c"file is not open for writing");
+        }
+        let Some(Change::File { data, modified }) = self.changes.get_mut(&handle.path) else {
+            return Err(c"no such file");
+        };
+        let end = handle
+            .position
+            .checked_add(buf.len())
+            .ok_or(c"not enough space")?;
+        // overwriting bytes the file already has takes no extra space
+        let grown = end.saturating_sub(data.len());
+        if self.used + grown > self.capacity {
+            return Err(c"not enough space");
+        }
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[handle.position..end].copy_from_slice(buf);
+        handle.position = end;
+        self.used += grown;
+        *modified = get_time() as u64;
+        Ok(())
+    }
+
+    fn seek(&mut self, fd: usize, whence: &str, offset: i64) -> Result<usize, &'static CStr> {
+        let Some(handle) = self.handle(fd) else {
+            return Err(c"bad file descriptor");
+        };
+        let len = self.file(&handle.path).map_or(0, <[u8]>::len);
+        let origin = match whence {
+            "set" => 0,
+            "cur" => handle.position,
+            "end" => len,
+            _ => return Err(c"invalid mode"),
+        };
+        let Some(position) = origin.checked_add_signed(offset as isize) else {
+            return Err(c"invalid offset");
+        };
+        if let Some(Some(handle)) = self.handles.get_mut(fd - 1) {
+            handle.position = position;
+        }
+        Ok(position)
+    }
+
+    fn collect_entries<'a>(&'a self, path: &str, entries: &mut Vec<TarEntry<'a>>) {
+        for name in self.list(path).unwrap_or_default() {
+            let child = child_path(path, name.trim_end_matches('/'));
+            let modified = self.modified(&child).unwrap_or_default();
+            let data = self.file(&child);
+            entries.push(TarEntry {
+                path: child.clone(),
+                data,
+                modified,
+            });
+            if data.is_none() {
+                self.collect_entries(&child, entries);
+            }
+        }
+    }
+
+    /// Writes everything visible in the overlay out as a ustar archive, with modification times.
+    pub fn export(&self) -> Vec<u8> {
+        let mut entries = Vec::new();
+        self.collect_entries("", &mut entries);
+        write_tar(&entries)
+    }
+}
+
+unsafe fn overlay<'a>(userdata: *mut c_void) -> &'a mut Overlay {
+    unsafe { &mut *userdata.cast() }
+}
+
+unsafe extern "C" fn fs_deinit(userdata: *mut c_void) {
+    drop(unsafe { Box::from_raw(userdata.cast::<Overlay>()) });
+}
+unsafe extern "C" fn fs_get_label(userdata: *mut c_void, buf: *mut c_char, buflen: *mut usize) {
+    unsafe { get_label(&overlay(userdata).label, buf, buflen) };
+}
+unsafe extern "C" fn fs_set_label(userdata: *mut c_void, buf: *const c_char, buflen: usize) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    fs.label = unsafe { label_arg(buf, buflen) }.to_vec();
+    fs.label.len()
+}
+unsafe extern "C" fn fs_space_used(userdata: *mut c_void) -> usize {
+    unsafe { overlay(userdata) }.used
+}
+unsafe extern "C" fn fs_is_read_only(_userdata: *mut c_void) -> bool {
+    false
+}
+unsafe extern "C" fn fs_size(userdata: *mut c_void, path: *const c_char, _err: nn_errorbuf_t) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    fs.file(&unsafe { path_arg(path) }).map_or(0, <[u8]>::len)
+}
+unsafe extern "C" fn fs_remove(userdata: *mut c_void, path: *const c_char, _err: nn_errorbuf_t) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    fs.remove(&unsafe { path_arg(path) }) as usize
+}
+unsafe extern "C" fn fs_last_modified(
+    userdata: *mut c_void,
+    path: *const c_char,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    match fs.modified(&unsafe { path_arg(path) }) {
+        Some(modified) => modified as usize,
+        None => {
+            unsafe { set_error(err, c"no such file or directory") };
+            0
+        }
+    }
+}
+unsafe extern "C" fn fs_rename(
+    userdata: *mut c_void,
+    from: *const c_char,
+    to: *const c_char,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    match fs.rename(&unsafe { path_arg(from) }, &unsafe { path_arg(to) }) {
+        Ok(()) => 1,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            0
+        }
+    }
+}
+unsafe extern "C" fn fs_exists(userdata: *mut c_void, path: *const c_char, _err: nn_errorbuf_t) -> bool {
+    unsafe { overlay(userdata) }.exists(&unsafe { path_arg(path) })
+}
+unsafe extern "C" fn fs_is_directory(
+    userdata: *mut c_void,
+    path: *const c_char,
+    _err: nn_errorbuf_t,
+) -> bool {
+    unsafe { overlay(userdata) }.is_directory(&unsafe { path_arg(path) })
+}
+unsafe extern "C" fn fs_make_directory(
+    userdata: *mut c_void,
+    path: *const c_char,
+    err: nn_errorbuf_t,
+) -> bool {
+    let fs = unsafe { overlay(userdata) };
+    match fs.make_directory(&unsafe { path_arg(path) }) {
+        Ok(created) => created,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            false
+        }
+    }
+}
+unsafe extern "C" fn fs_list(
+    alloc: *mut nn_Alloc,
+    userdata: *mut c_void,
+    path: *const c_char,
+    len: *mut usize,
+    err: nn_errorbuf_t,
+) -> *mut *mut c_char {
+    let fs = unsafe { overlay(userdata) };
+    let Some(names) = fs.list(&unsafe { path_arg(path) }) else {
+        unsafe { set_error(err, c"no such directory") };
+        return null_mut();
+    };
+    unsafe { name_list(alloc, &names, len, err) }
+}
+unsafe extern "C" fn fs_open(
+    userdata: *mut c_void,
+    path: *const c_char,
+    mode: *const c_char,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    let mode = unsafe { CStr::from_ptr(mode) }.to_string_lossy();
+    match fs.open(&unsafe { path_arg(path) }, &mode) {
+        Ok(fd) => fd,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            0
+        }
+    }
+}
+unsafe extern "C" fn fs_close(userdata: *mut c_void, fd: usize, err: nn_errorbuf_t) -> bool {
+    let closed = unsafe { overlay(userdata) }.close(fd);
+    if !closed {
+        unsafe { set_error(err, c"bad file descriptor") };
+    }
+    closed
+}
+unsafe extern "C" fn fs_write(
+    userdata: *mut c_void,
+    fd: usize,
+    buf: *const c_char,
+    len: usize,
+    err: nn_errorbuf_t,
+) -> bool {
+    let fs = unsafe { overlay(userdata) };
+    let buf = unsafe { std::slice::from_raw_parts(buf.cast::<u8>(), len) };
+    match fs.write(fd, buf) {
+        Ok(()) => true,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            false
+        }
+    }
+}
+unsafe extern "C" fn fs_read(
+    userdata: *mut c_void,
+    fd: usize,
+    buf: *mut c_char,
+    required: usize,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf.cast::<u8>(), required) };
+    match fs.read(fd, buf) {
+        Ok(len) => len,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            0
+        }
+    }
+}
+unsafe extern "C" fn fs_seek(
+    userdata: *mut c_void,
+    fd: usize,
+    whence: *const c_char,
+    off: c_int,
+    err: nn_errorbuf_t,
+) -> usize {
+    let fs = unsafe { overlay(userdata) };
+    let whence = unsafe { CStr::from_ptr(whence) }.to_string_lossy();
+    match fs.seek(fd, &whence, off as i64) {
+        Ok(position) => position,
+        Err(msg) => {
+            unsafe { set_error(err, msg) };
+            0
+        }
+    }
+}
+
+/// Builds the neonucleus filesystem table for an overlay. The table owns `overlay` and frees it on deinit.
+pub fn overlay_table(overlay: *mut Overlay) -> nn_filesystemTable {
+    nn_filesystemTable {
+        userdata: overlay.cast(),
+        deinit: Some(fs_deinit),
+        getLabel: Some(fs_get_label),
+        setLabel: Some(fs_set_label),
+        spaceUsed: Some(fs_space_used),
+        spaceTotal: unsafe { (*overlay).capacity },
+        isReadOnly: Some(fs_is_read_only),
+        size: Some(fs_size),
+        remove: Some(fs_remove),
+        lastModified: Some(fs_last_modified),
+        rename: Some(fs_rename),
+        exists: Some(fs_exists),
+        isDirectory: Some(fs_is_directory),
+        makeDirectory: Some(fs_make_directory),
+        list: Some(fs_list),
+        open: Some(fs_open),
+        close: Some(fs_close),
+        write: Some(fs_write),
+        read: Some(fs_read),
+        seek: Some(fs_seek),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Rc<Node> {
+        let big = Node::File {
+            data: vec![0; 100],
+            modified: 0,
+        };
+        Rc::new(Node::Directory {
+            children: BTreeMap::from([("big".to_owned(), big)]),
+            modified: 0,
+        })
+    }
+
+    #[test]
+    fn base_entries_keep_their_times() {
+        let file = Node::File {
+            data: Vec::new(),
+            modified: 1700000000,
+        };
+        let dir = Node::Directory {
+            children: BTreeMap::from([("file".to_owned(), file)]),
+            modified: 1600000000,
+        };
+        let overlay = Overlay::new(
+            Rc::new(Node::Directory {
+                children: BTreeMap::from([("dir".to_owned(), dir)]),
+                modified: 0,
+            }),
+            0,
+        );
+        assert_eq!(overlay.modified("dir"), Some(1600000000));
+        assert_eq!(overlay.modified("dir/file"), Some(1700000000));
+        assert_eq!(overlay.modified("missing"), None);
+    }
+
+    #[test]
+    fn base_image_does_not_count() {
+        let mut overlay = Overlay::new(base(), 10);
+        let fd = overlay.open("new", "w").unwrap();
+        assert_eq!(overlay.write(fd, &[1; 10]), Ok(()));
+        assert_eq!(overlay.used, 10);
+        assert!(overlay.write(fd, &[1]).is_err());
+        assert_eq!(overlay.used, 10);
+    }
+
+    #[test]
+    fn overwriting_takes_no_space() {
+        let mut overlay = Overlay::new(base(), 8);
+        let fd = overlay.open("file", "w").unwrap();
+        overlay.write(fd, &[1; 8]).unwrap();
+        overlay.seek(fd, "set", 2).unwrap();
+        assert_eq!(overlay.write(fd, &[2; 6]), Ok(()));
+        assert_eq!(overlay.used, 8);
+    }
+
+    #[test]
+    fn truncating_and_removing_free_space() {
+        let mut overlay = Overlay::new(base(), 100);
+        overlay.make_directory("dir").unwrap();
+        let fd = overlay.open("dir/file", "w").unwrap();
+        overlay.write(fd, &[1; 40]).unwrap();
+        overlay.close(fd);
+        assert_eq!(overlay.used, 40);
+
+        let fd = overlay.open("dir/file", "w").unwrap();
+        assert_eq!(overlay.used, 0);
+        overlay.write(fd, &[1; 5]).unwrap();
+        overlay.close(fd);
+        assert!(overlay.remove("dir"));
+        assert_eq!(overlay.used, 0);
+
+        let fd = overlay.open("file", "w").unwrap();
+        overlay.write(fd, &[1; 5]).unwrap();
+        overlay.reset();
+        assert_eq!(overlay.used, 0);
+    }
+
+    #[test]
+    fn appending_to_base_file_copies_it() {
+        let mut overlay = Overlay::new(base(), 50);
+        assert!(overlay.open("big", "a").is_err());
+        let mut overlay = Overlay::new(base(), 150);
+        overlay.open("big", "a").unwrap();
+        assert_eq!(overlay.used, 100);
+    }
+
+    fn base_with_directory() -> Rc<Node> {
+        let file = Node::File {
+            data: b"orig".to_vec(),
+            modified: 0,
+        };
+        let dir = Node::Directory {
+            children: BTreeMap::from([("file".to_owned(), file)]),
+            modified: 0,
+        };
+        Rc::new(Node::Directory {
+            children: BTreeMap::from([("dir".to_owned(), dir)]),
+            modified: 0,
+        })
+    }
+
+    #[test]
+    fn removing_an_overwritten_base_file_keeps_it_gone() {
+        let mut overlay = Overlay::new(base_with_directory(), 100);
+        let fd = overlay.open("dir/file", "w").unwrap();
+        overlay.write(fd, b"new").unwrap();
+        overlay.close(fd);
+        assert!(overlay.remove("dir/file"));
+        assert_eq!(overlay.file("dir/file"), None);
+        assert_eq!(overlay.list("dir"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn removing_a_recreated_base_directory_keeps_it_gone() {
+        let mut overlay = Overlay::new(base_with_directory(), 100);
+        assert!(overlay.remove("dir"));
+        assert_eq!(overlay.make_directory("dir"), Ok(true));
+        assert!(!overlay.exists("dir/file"));
+        assert!(overlay.remove("dir"));
+        assert!(!overlay.exists("dir"));
+        assert!(!overlay.exists("dir/file"));
+    }
+
+    #[test]
+    fn renaming_a_modified_base_file_moves_it() {
+        let mut overlay = Overlay::new(base_with_directory(), 100);
+        let fd = overlay.open("dir/file", "a").unwrap();
+        overlay.write(fd, b"!").unwrap();
+        overlay.close(fd);
+        assert_eq!(overlay.rename("dir/file", "moved"), Ok(()));
+        assert!(!overlay.exists("dir/file"));
+        assert_eq!(overlay.file("moved"), Some(&b"orig!"[..]));
+        assert_eq!(overlay.used, 5);
+    }
+
+    #[test]
+    fn renaming_a_base_directory_counts_its_copy() {
+        let mut overlay = Overlay::new(base_with_directory(), 3);
+        assert!(overlay.rename("dir", "moved").is_err());
+        assert!(overlay.exists("dir/file"));
+        assert_eq!(overlay.used, 0);
+
+        let mut overlay = Overlay::new(base_with_directory(), 4);
+        assert_eq!(overlay.rename("dir", "moved"), Ok(()));
+        assert_eq!(overlay.file("moved/file"), Some(&b"orig"[..]));
+        assert!(!overlay.exists("dir"));
+        assert_eq!(overlay.used, 4);
+    }
+
+    #[test]
+    fn descriptors_start_at_one() {
+        let mut overlay = Overlay::new(base(), 10);
+        assert_eq!(overlay.open("big", "r"), Ok(1));
+        assert!(!overlay.close(0));
+        assert!(overlay.read(0, &mut [0; 4]).is_err());
+        assert!(overlay.close(1));
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    ffi::{CStr, c_void},
+    ptr::null_mut,
+};
+
+use neonucleus::ffi::{
+    NN_VALUE_INT, NN_VALUE_NIL, nn_component, nn_componentTable, nn_computer, nn_defineMethod,
+    nn_getAllocator, nn_getArgument, nn_getArgumentCount, nn_getUniverse, nn_newComponent, nn_newComponentTable,
+    nn_return, nn_setCError, nn_toString, nn_universe, nn_values_getType, nn_values_integer,
+    nn_values_string,
+};
+
+use crate::{
+    devices::{DeviceInfo, set_component_info},
+    fields::{Field, define_fields},
+    value::{ValueType, new_value},
+};
+
+static mut PROBE_TABLE: *mut nn_componentTable = null_mut();
+
+/// Fields of the `probe` component: `value` can be read and assigned, `writes` only read.
+const FIELDS: &[Field] = &[
+    Field {
+        name: c"value",
+        getter: true,
+        setter: true,
+    },
+    Field {
+        name: c"writes",
+        getter: true,
+        setter: false,
+    },
+];
+
+/// A component for checking the Lua component bridge, only built with the `probe` feature. Its fields go through component.fields
+/// and proxy field access, and its counters are value objects, neither of which any OC
+/// component in this tree has.
+#[derive(Default)]
+pub struct Probe {
+    value: Vec<u8>,
+    writes: i64,
+}
+
+unsafe fn probe<'a>(userdata: *mut c_void) -> &'a mut Probe {
+    unsafe { &mut *userdata.cast() }
+}
+
+unsafe extern "C" fn probe_destroy(
+    _table_userdata: *mut c_void,
+    _component: *mut nn_component,
+    userdata: *mut c_void,
+) {
+    drop(unsafe { Box::from_raw(userdata.cast::<Probe>()) });
+}
+/// Returns the value when called with no arguments, as a getter, and sets it when given one.
+unsafe extern "C" fn probe_value(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let probe = unsafe { probe(userdata) };
+    if unsafe { nn_getArgumentCount(computer) } == 0 {
+        let alloc = unsafe { nn_getAllocator(nn_getUniverse(computer)) };
+        let value = &probe.value;
+        unsafe {
+            nn_return(
+                computer,
+                nn_values_string(alloc, value.as_ptr().cast(), value.len()),
+            )
+        };
+        return;
+    }
+    let mut len = 0;
+    let ptr = unsafe { nn_toString(nn_getArgument(computer, 0), &raw mut len) };
+    if ptr.is_null() {
+        return unsafe { nn_setCError(computer, c"bad argument (string expected)".as_ptr()) };
+    }
+    probe.value = unsafe { std::slice::from_raw_parts(ptr.cast(), len) }.to_vec();
+    probe.writes += 1;
+}
+unsafe extern "C" fn probe_writes(
+    userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let probe = unsafe { probe(userdata) };
+    unsafe { nn_return(computer, nn_values_integer(probe.writes)) };
+}
+
+/// Returns a new counter, a value object starting at 0.
+unsafe extern "C" fn probe_counter(
+    _userdata: *mut c_void,
+    _method_userdata: *mut c_void,
+    _component: *mut nn_component,
+    computer: *mut nn_computer,
+) {
+    let counter = Box::into_raw(Box::new(0i64));
+    unsafe { nn_return(computer, new_value(computer, &COUNTER, counter.cast())) };
+}
+
+static COUNTER: ValueType = ValueType {
+    name: c"counter",
+    methods: &[(c"increment", counter_increment), (c"get", counter_get)],
+    dispose: counter_dispose,
+};
+
+/// Adds 1, or the integer given, and returns the new count.
+unsafe fn counter_increment(userdata: *mut c_void, computer: *mut nn_computer) {
+    let count = unsafe { &mut *userdata.cast::<i64>() };
+    let arg = unsafe { nn_getArgument(computer, 0) };
+    *count += match unsafe { nn_values_getType(arg) } as i32 {
+        NN_VALUE_NIL => 1,
+        NN_VALUE_INT => unsafe { arg.__bindgen_anon_1.integer },
+        _ => return unsafe { nn_setCError(computer, c"bad argument (integer expected)".as_ptr()) },
+    };
+    unsafe { nn_return(computer, nn_values_integer(*count)) };
+}
+unsafe fn counter_get(userdata: *mut c_void, computer: *mut nn_computer) {
+    unsafe { nn_return(computer, nn_values_integer(*userdata.cast::<i64>())) };
+}
+unsafe fn counter_dispose(userdata: *mut c_void) {
+    drop(unsafe { Box::from_raw(userdata.cast::<i64>()) });
+}
+
+type Method = unsafe extern "C" fn(*mut c_void, *mut c_void, *mut nn_component, *mut nn_computer);
+
+const METHODS: [(&CStr, Method, &CStr); 3] = [
+    (c"value", probe_value, c"string -- A value that can be read and assigned."),
+    (c"writes", probe_writes, c"number -- How many times value was assigned."),
+    (c"counter", probe_counter, c"counter():userdata -- A new counter, with increment([n:integer]):number and get():number."),
+];
+
+/// Registers the `probe` component type. Called once from init.
+pub fn init_probe_table(universe: *mut nn_universe) {
+    let alloc = unsafe { nn_getAllocator(universe) };
+    let table = unsafe {
+        nn_newComponentTable(
+            alloc,
+            c"probe".as_ptr(),
+            null_mut(),
+            None,
+            Some(probe_destroy),
+        )
+    };
+    assert_ne!(table, null_mut());
+    for (name, method, doc) in METHODS {
+        unsafe {
+            nn_defineMethod(
+                table,
+                name.as_ptr(),
+                true,
+                Some(method),
+                null_mut(),
+                doc.as_ptr(),
+            )
+        };
+    }
+    define_fields(table, FIELDS);
+    unsafe { PROBE_TABLE = table };
+}
+
+/// Adds a probe to the computer. Returns false if it has no room for it.
+///
+/// # Safety
+/// computer must be valid and init_probe_table must have been called
+pub unsafe fn add_probe_component(computer: *mut nn_computer, slot: i32) -> bool {
+    let probe = Box::into_raw(Box::new(Probe::default()));
+    let component =
+        unsafe { nn_newComponent(computer, null_mut(), slot, PROBE_TABLE, probe.cast()) };
+    if component.is_null() {
+        drop(unsafe { Box::from_raw(probe) });
+        return false;
+    }
+    let info = DeviceInfo::new(c"generic", c"Probe", c"Probe");
+    unsafe { set_component_info(computer, component, info) };
+    true
+}
@@ -0,0 +1,323 @@
+use std::{collections::VecDeque, ffi::CStr};
+
+use crate::context::Deterministic;
+
+const MAGIC: &[u8; 4] = b"NWRP";
+const VERSION: u8 = 1;
+
+const KEY_DOWN: u8 = 0;
+const KEY_UP: u8 = 1;
+const INSERT_FLOPPY: u8 = 2;
+const EJECT_FLOPPY: u8 = 3;
+
+/// Something from outside the machine that changes what it computes.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Key {
+        char: u32,
+        code: u32,
+        released: bool,
+    },
+    /// Disk drives are numbered in the order they were added to the computer.
+    InsertFloppy {
+        drive: usize,
+        image: Vec<u8>,
+    },
+    EjectFloppy {
+        drive: usize,
+    },
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, &'static CStr> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(c"replay is truncated")?;
+        *bytes = rest;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(c"replay is malformed")
+}
+
+/// Reads a varint that must fit in T.
+fn read_int<T: TryFrom<u64>>(bytes: &mut &[u8]) -> Result<T, &'static CStr> {
+    read_varint(bytes)?
+        .try_into()
+        .map_err(|_| c"replay is malformed")
+}
+
+fn read_bytes<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static CStr> {
+    if bytes.len() < len {
+        return Err(c"replay is truncated");
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn read_f64(bytes: &mut &[u8]) -> Result<f64, &'static CStr> {
+    Ok(f64::from_le_bytes(
+        read_bytes(bytes, 8)?.try_into().unwrap(),
+    ))
+}
+
+/// A log of inputs, each stored with the number of ticks since the previous one.
+///
+/// The header holds the deterministic settings, since a replay only matches the recording when
+/// the clock and RNG do. Recordings start before the computer's first tick, so the settings are
+/// the whole start state.
+pub struct Recording {
+    log: Vec<u8>,
+    last_tick: u64,
+}
+
+impl Recording {
+    pub fn new(settings: Deterministic) -> Self {
+        let mut log = Vec::new();
+        log.extend_from_slice(MAGIC);
+        log.push(VERSION);
+        log.extend_from_slice(&settings.seed.to_le_bytes());
+        log.extend_from_slice(&settings.start.to_le_bytes());
+        log.extend_from_slice(&settings.step.to_le_bytes());
+        Self { log, last_tick: 0 }
+    }
+
+    /// Adds an input that arrived before tick `tick`.
+    pub fn record(&mut self, tick: u64, input: &Input) {
+        write_varint(&mut self.log, tick - self.last_tick);
+        self.last_tick = tick;
+        match input {
+            Input::Key {
+                char,
+                code,
+                released,
+            } => {
+                self.log.push(if *released { KEY_UP } else { KEY_DOWN });
+                write_varint(&mut self.log, *char as u64);
+                write_varint(&mut self.log, *code as u64);
+            }
+            Input::InsertFloppy { drive, image } => {
+                self.log.push(INSERT_FLOPPY);
+                write_varint(&mut self.log, *drive as u64);
+                write_varint(&mut self.log, image.len() as u64);
+                self.log.extend_from_slice(image);
+            }
+            Input::EjectFloppy { drive } => {
+                self.log.push(EJECT_FLOPPY);
+                write_varint(&mut self.log, *drive as u64);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.log
+    }
+}
+
+/// The inputs of a recording still to be applied, by tick.
+pub struct Replay {
+    inputs: VecDeque<(u64, Input)>,
+}
+
+impl Replay {
+    pub fn parse(mut bytes: &[u8], settings: Deterministic) -> Result<Self, &'static CStr> {
+        let bytes = &mut bytes;
+        if read_bytes(bytes, 4)? != MAGIC {
+            return Err(c"not a replay");
+        }
+        if read_bytes(bytes, 1)?[0] != VERSION {
+            return Err(c"unsupported replay version");
+        }
+        let recorded = Deterministic {
+            seed: u64::from_le_bytes(read_bytes(bytes, 8)?.try_into().unwrap()),
+            start: read_f64(bytes)?,
+            step: read_f64(bytes)?,
+        };
+        if recorded != settings {
+            return Err(c"replay was recorded with different deterministic settings");
+        }
+
+        let mut inputs = VecDeque::new();
+        let mut tick = 0u64;
+        while !bytes.is_empty() {
+            tick = tick
+                .checked_add(read_varint(bytes)?)
+                .ok_or(c"replay is malformed")?;
+            let kind = read_bytes(bytes, 1)?[0];
+            let input = match kind {
+                KEY_DOWN | KEY_UP => Input::Key {
+                    char: read_int(bytes)?,
+                    code: read_int(bytes)?,
+                    released: kind == KEY_UP,
+                },
+                INSERT_FLOPPY => {
+                    let drive = read_int(bytes)?;
+                    let len = read_int(bytes)?;
+                    Input::InsertFloppy {
+                        drive,
+                        image: read_bytes(bytes, len)?.to_vec(),
+                    }
+                }
+                EJECT_FLOPPY => Input::EjectFloppy {
+                    drive: read_int(bytes)?,
+                },
+                _ => return Err(c"replay is malformed"),
+            };
+            inputs.push_back((tick, input));
+        }
+        Ok(Self { inputs })
+    }
+
+    /// Takes the next input due before tick `tick`.
+    pub fn next_due(&mut self, tick: u64) -> Option<Input> {
+        if self.inputs.front()?.0 > tick {
+            return None;
+        }
+        self.inputs.pop_front().map(|(_, input)| input)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        for n in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, n);
+            let mut bytes = out.as_slice();
+            assert_eq!(read_varint(&mut bytes), Ok(n));
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn varints_are_little_endian_groups_of_seven_bits() {
+        let mut out = Vec::new();
+        write_varint(&mut out, 300);
+        assert_eq!(out, [0xac, 0x02]);
+        write_varint(&mut out, u64::MAX);
+        assert_eq!(out.len(), 2 + 10);
+    }
+
+    #[test]
+    fn bad_varints_are_errors() {
+        assert_eq!(
+            read_varint(&mut &[0x80, 0x80][..]),
+            Err(c"replay is truncated")
+        );
+        assert_eq!(read_varint(&mut &[][..]), Err(c"replay is truncated"));
+        assert_eq!(
+            read_varint(&mut &[0xff; 11][..]),
+            Err(c"replay is malformed")
+        );
+    }
+
+    const SETTINGS: Deterministic = Deterministic {
+        seed: 42,
+        start: 0.0,
+        step: 0.05,
+    };
+
+    fn inputs() -> Vec<(u64, Input)> {
+        vec![
+            (
+                0,
+                Input::Key {
+                    char: 'a' as u32,
+                    code: 30,
+                    released: false,
+                },
+            ),
+            (
+                0,
+                Input::Key {
+                    char: 'a' as u32,
+                    code: 30,
+                    released: true,
+                },
+            ),
+            (
+                3,
+                Input::InsertFloppy {
+                    drive: 1,
+                    image: vec![1, 2, 3],
+                },
+            ),
+            (200, Input::EjectFloppy { drive: 1 }),
+            (
+                200,
+                Input::Key {
+                    char: 0x1f600,
+                    code: u32::MAX,
+                    released: false,
+                },
+            ),
+        ]
+    }
+
+    fn record() -> Vec<u8> {
+        let mut recording = Recording::new(SETTINGS);
+        for (tick, input) in &inputs() {
+            recording.record(*tick, input);
+        }
+        recording.finish()
+    }
+
+    #[test]
+    fn recordings_replay_their_inputs_at_the_same_ticks() {
+        let log = record();
+        let mut replay = Replay::parse(&log, SETTINGS).unwrap();
+        let mut replayed = Vec::new();
+        for tick in 0..=200 {
+            while let Some(input) = replay.next_due(tick) {
+                replayed.push((tick, input));
+            }
+        }
+        assert!(replay.is_finished());
+        assert_eq!(replayed, inputs());
+    }
+
+    #[test]
+    fn replays_need_the_recorded_settings() {
+        let log = record();
+        let other = Deterministic {
+            seed: 43,
+            ..SETTINGS
+        };
+        assert!(matches!(
+            Replay::parse(&log, other),
+            Err(msg) if msg == c"replay was recorded with different deterministic settings"
+        ));
+        let other = Deterministic {
+            step: 0.1,
+            ..SETTINGS
+        };
+        assert!(Replay::parse(&log, other).is_err());
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_malformed() {
+        let mut log = Recording::new(SETTINGS).finish();
+        write_varint(&mut log, 0);
+        log.push(KEY_DOWN);
+        write_varint(&mut log, u32::MAX as u64 + 1);
+        write_varint(&mut log, 0);
+        assert!(matches!(Replay::parse(&log, SETTINGS), Err(msg) if msg == c"replay is malformed"));
+    }
+}
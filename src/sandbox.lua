@@ -25,41 +25,26 @@ local function spcall(f, ...)
     return nil, t[2]
 end
 
-local function nextDeadline()
-    return computer.uptime() + 5
-end
-
 local bubbleYield = false
-local timeout = nextDeadline()
 
-local function tooLong()
-    return computer.uptime() >= timeout
-end
+-- the instruction watchdog in arch.rs raises the error itself and heats the computer up
+local tooLong = computer.isTimedOut
+local preempted = computer.isPreempted
 
 local tooLongWithoutYielding = "too long without yielding"
 
-local function nextHeatUp()
-    return computer.uptime() + math.random() * 2 + 0.1
-end
-
-local heatInc = nextHeatUp()
-
-debug.sethook(function()
-    if computer.uptime() >= heatInc then
-        heatInc = nextHeatUp()
-        computer.addHeat(math.random() * 3)
-    end
-
-    if tooLong() and not bubbleYield then
-        bubbleYield = true
-        error(tooLongWithoutYielding) -- here it is an actual string
+-- once the watchdog has raised it, the machine must die, so pcall passes it on like in OC
+local function checkDeadline()
+    if tooLong() then
+        error(tooLongWithoutYielding, 0)
     end
-end, "c", 100000) -- no bogo mips, the check is cheap anyways
+end
 
 local function resume(co, val1, ...)
     while true do
         local t = {coroutine.resume(co, val1, ...)}
-        if bubbleYield then -- yield was meaningless
+        -- the watchdog preempts whichever coroutine runs when the tick's budget is spent
+        if bubbleYield or preempted() then -- yield was meaningless
             coroutine.yield() -- carry through
         else
             return table.unpack(t) -- yield the user cares about
@@ -92,7 +77,28 @@ end
 
 local libcomponent
 
+-- the fields of each proxy, kept out of the proxy so they can't shadow a field named "fields"
+local proxyFields = setmetatable({}, {__mode = "k"})
+
 local componentProxy = {
+    __index = function(self, key)
+        local field = (proxyFields[self] or {})[key]
+        if field and field.getter then
+            return libcomponent.invoke(rawget(self, "address"), key)
+        end
+    end,
+    __newindex = function(self, key, value)
+        local field = (proxyFields[self] or {})[key]
+        if field then
+            if not field.setter then
+                error("field is read only", 2)
+            end
+            -- value is passed even when nil, so the method gets one argument and sets instead of reading
+            libcomponent.invoke(rawget(self, "address"), key, value)
+        else
+            rawset(self, key, value)
+        end
+    end,
     __pairs = function(self)
         local method
         return function()
@@ -123,7 +129,7 @@ libcomponent = {
             computer.clearError()
 
             -- in this situation, either the temperature is above 100 C and we throttle
-            -- or the call budget has been filled and we dont care
+            -- or the call budget has been filled or the call was indirect, and we wait a tick
             if computer.isOverheating() or computer.isOverworked() then
                 local ok = pcall(yield)
                 assert(ok, "component explicitly requested to be suspended")
@@ -191,8 +197,8 @@ libcomponent = {
             address = addr,
             type = component.type(addr),
             slot = component.slot(addr),
-            fields = {},
         }, componentProxy)
+        proxyFields[proxy] = component.fields(addr)
         local methods = component.methods(addr)
         for method in pairs(methods) do
             proxy[method] = setmetatable({address = addr, name = method}, componentCallback)
@@ -236,9 +242,7 @@ local libcomputer = {
         until computer.uptime() >= deadline
     end,
     beep = computer.beep,
-    getDeviceInfo = function()
-        return {} -- yup
-    end,
+    getDeviceInfo = computer.getDeviceInfo,
     getProgramLocations = function()
         return {} -- yup
     end,
@@ -269,12 +273,10 @@ sandbox = {
     next = next,
     pairs = pairs,
     pcall = function(...)
-        if tooLong() then
-            yield()
-            return false, tooLongWithoutYielding
-        end
+        checkDeadline()
         local t = {pcall(...)}
-        ensureYields() -- if it took too long, this will make it yield
+        checkDeadline()
+        ensureYields()
         return table.unpack(t)
     end,
     rawequal = rawequal,
@@ -301,10 +303,7 @@ sandbox = {
         -- to prevent infinite loops we simply terminate the error handler if it took too long.
         local function errorCapture(ff, ...)
             --ensureYields() -- you can't yield in xpcall...
-            -- Immediately dont care
-            if tooLong() then
-                return nil, tooLongWithoutYielding
-            end
+            checkDeadline()
             -- This would mean you shutdown in the errorCapture.
             -- In vanilla OC, that does nothing.
             -- In here, it returns a suspended error and then yields eventually.
@@ -322,6 +321,7 @@ sandbox = {
         end
 
         local t = {errorCapture(f, ...)}
+        checkDeadline()
         pcall(ensureYields) -- it can fail if we are doing xpcall in xpcall.
         return table.unpack(t)
     end,
@@ -384,7 +384,7 @@ sandbox = {
               namewhat = result.namewhat,
               istailcall = result.istailcall,
               -- believe it or not, this IS NOT safe.
-              -- They may use this to re-call machine.lua which would reset the hook and timeout.
+              -- They may use this to re-call machine.lua which would reset the environment.
               -- TODO: make this safe.
               --func = result.func,
             }
@@ -437,7 +437,6 @@ local gcInterval = 0.25
 local lastGC = computer.uptime()
 
 while true do
-    timeout = nextDeadline()
     bubbleYield = false
     collectgarbage()
 
@@ -0,0 +1,245 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{CStr, CString, c_char, c_void},
+};
+
+use neonucleus::ffi::{nn_computer, nn_value, nn_values_cstring};
+
+/// Reads its arguments with nn_getArgument and answers with nn_return, like a component method.
+pub type ValueMethod = unsafe fn(userdata: *mut c_void, computer: *mut nn_computer);
+
+/// A kind of value object, such as an internet request handle. Lua sees instances as userdata
+/// whose methods call back into these functions.
+pub struct ValueType {
+    pub name: &'static CStr,
+    pub methods: &'static [(&'static CStr, ValueMethod)],
+    pub dispose: unsafe fn(userdata: *mut c_void),
+}
+
+/// Identifies a value object among its computer's. Lua userdata for the object hold one.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(transparent)]
+pub struct ValueHandle(u64);
+
+struct Value {
+    ty: &'static ValueType,
+    userdata: *mut c_void,
+    /// neonucleus has no userdata kind, so the object travels as an nn_value C string. It is
+    /// recognised by this allocation's address, never by its contents, which are the type name.
+    tag: CString,
+    /// Lua userdata currently referring to this object.
+    refs: usize,
+}
+
+/// The value objects of one computer. Whatever is left is disposed of with it.
+#[derive(Default)]
+pub struct Values {
+    next: u64,
+    live: BTreeMap<ValueHandle, Value>,
+    tags: BTreeMap<*const c_char, ValueHandle>,
+    /// Objects created since the last sweep, which are gone unless pushed to Lua by then.
+    unclaimed: Vec<ValueHandle>,
+}
+
+impl Values {
+    /// Registers userdata as a value object. It is disposed once every Lua reference to it is
+    /// garbage-collected, or by the next sweep if it was never pushed to Lua.
+    pub fn insert(&mut self, ty: &'static ValueType, userdata: *mut c_void) -> ValueHandle {
+        let handle = ValueHandle(self.next);
+        self.next += 1;
+        let tag = ty.name.to_owned();
+        self.tags.insert(tag.as_ptr(), handle);
+        self.unclaimed.push(handle);
+        self.live.insert(
+            handle,
+            Value {
+                ty,
+                userdata,
+                tag,
+                refs: 0,
+            },
+        );
+        handle
+    }
+
+    /// The C string that stands for the object in an nn_value.
+    pub fn tag(&self, handle: ValueHandle) -> Option<&CStr> {
+        Some(&self.live.get(&handle)?.tag)
+    }
+
+    /// Returns the handle of the live value object a C string value stands for, if it is one.
+    /// Only the pointer from [`Values::tag`] matches, so Lua strings can't forge one.
+    pub fn lookup(&self, cstring: *const c_char) -> Option<ValueHandle> {
+        self.tags.get(&cstring).copied()
+    }
+
+    pub fn type_name(&self, handle: ValueHandle) -> Option<&'static CStr> {
+        Some(self.live.get(&handle)?.ty.name)
+    }
+
+    pub fn userdata(&self, handle: ValueHandle) -> Option<*mut c_void> {
+        Some(self.live.get(&handle)?.userdata)
+    }
+
+    pub fn method(&self, handle: ValueHandle, name: &CStr) -> Option<ValueMethod> {
+        let ty = self.live.get(&handle)?.ty;
+        ty.methods
+            .iter()
+            .find(|(method, _)| *method == name)
+            .map(|&(_, method)| method)
+    }
+
+    /// Called whenever a Lua userdata for the object is created. Returns false if it is gone.
+    pub fn retain(&mut self, handle: ValueHandle) -> bool {
+        match self.live.get_mut(&handle) {
+            Some(value) => {
+                value.refs += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called from a userdata's __gc. Disposes of the object once nothing refers to it.
+    pub fn release(&mut self, handle: ValueHandle) {
+        let Some(value) = self.live.get_mut(&handle) else {
+            return;
+        };
+        value.refs = value.refs.saturating_sub(1);
+        if value.refs == 0 {
+            self.dispose(handle);
+        }
+    }
+
+    /// Disposes of the objects created since the last sweep that were never pushed to Lua.
+    /// Called at the end of every call that could have returned some.
+    pub fn sweep(&mut self) {
+        for handle in std::mem::take(&mut self.unclaimed) {
+            if self.live.get(&handle).is_some_and(|value| value.refs == 0) {
+                self.dispose(handle);
+            }
+        }
+    }
+
+    fn dispose(&mut self, handle: ValueHandle) {
+        let value = self.live.remove(&handle).unwrap();
+        self.tags.remove(&value.tag.as_ptr());
+        unsafe { (value.ty.dispose)(value.userdata) };
+    }
+}
+
+impl Drop for Values {
+    fn drop(&mut self) {
+        for value in self.live.values() {
+            unsafe { (value.ty.dispose)(value.userdata) };
+        }
+    }
+}
+
+/// Wraps userdata in a value object of the computer, to be returned from a component method.
+/// It must be returned from the call that created it, or it is disposed at the end of it.
+/// Only the probe creates value objects so far.
+///
+/// # Safety
+/// computer must be valid and created by new_computer, and ty's functions must be safe to call
+/// with userdata
+#[cfg_attr(not(feature = "probe"), allow(dead_code))]
+pub unsafe fn new_value(
+    computer: *mut nn_computer,
+    ty: &'static ValueType,
+    userdata: *mut c_void,
+) -> nn_value {
+    let values = unsafe { crate::values(computer) };
+    let handle = values.insert(ty, userdata);
+    unsafe { to_value(values, handle) }
+}
+
+/// The nn_value that passes the object back into a component call.
+///
+/// # Safety
+/// handle must be live in values
+pub unsafe fn to_value(values: &Values, handle: ValueHandle) -> nn_value {
+    unsafe { nn_values_cstring(values.tag(handle).unwrap().as_ptr()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    static DISPOSED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    unsafe fn dispose(userdata: *mut c_void) {
+        DISPOSED.lock().unwrap().push(userdata as usize);
+    }
+
+    unsafe fn ping(_userdata: *mut c_void, _computer: *mut nn_computer) {}
+
+    static TEST: ValueType = ValueType {
+        name: c"test",
+        methods: &[(c"ping", ping)],
+        dispose,
+    };
+
+    fn disposed() -> Vec<usize> {
+        DISPOSED.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn values_live_until_the_last_reference_is_collected() {
+        let mut values = Values::default();
+        let handle = values.insert(&TEST, std::ptr::without_provenance_mut(1));
+        let tag = values.tag(handle).unwrap().as_ptr();
+        assert_eq!(values.lookup(tag), Some(handle));
+        assert_eq!(values.type_name(handle), Some(c"test"));
+        assert!(values.method(handle, c"ping").is_some());
+        assert!(values.method(handle, c"pong").is_none());
+
+        assert!(values.retain(handle));
+        assert!(values.retain(handle));
+        values.release(handle);
+        assert_eq!(values.userdata(handle), Some(std::ptr::without_provenance_mut(1)));
+        values.release(handle);
+        assert_eq!(values.userdata(handle), None);
+        assert_eq!(values.lookup(tag), None);
+        assert!(!values.retain(handle));
+        assert!(disposed().contains(&1));
+
+        values.insert(&TEST, std::ptr::without_provenance_mut(2));
+        drop(values);
+        assert!(disposed().contains(&2));
+    }
+
+    #[test]
+    fn other_strings_are_not_values() {
+        let mut values = Values::default();
+        let handle = values.insert(&TEST, std::ptr::without_provenance_mut(3));
+        let copy = values.tag(handle).unwrap().to_owned();
+        assert_eq!(copy.as_c_str(), c"test");
+        assert_eq!(values.lookup(copy.as_ptr()), None);
+        assert_eq!(values.lookup(c"test".as_ptr()), None);
+        assert_eq!(values.lookup(values.tag(handle).unwrap().as_ptr()), Some(handle));
+    }
+
+    #[test]
+    fn sweeps_dispose_of_values_never_pushed() {
+        let mut values = Values::default();
+        let kept = values.insert(&TEST, std::ptr::without_provenance_mut(4));
+        let dropped = values.insert(&TEST, std::ptr::without_provenance_mut(5));
+        assert!(values.retain(kept));
+        values.sweep();
+        assert!(disposed().contains(&5));
+        assert!(!disposed().contains(&4));
+        assert_eq!(values.userdata(dropped), None);
+        assert_eq!(values.userdata(kept), Some(std::ptr::without_provenance_mut(4)));
+
+        // only objects created since the last sweep are checked
+        let later = values.insert(&TEST, std::ptr::without_provenance_mut(6));
+        values.sweep();
+        assert_eq!(values.userdata(later), None);
+        assert_eq!(values.userdata(kept), Some(std::ptr::without_provenance_mut(4)));
+        values.release(kept);
+        assert!(disposed().contains(&4));
+    }
+}